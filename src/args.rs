@@ -0,0 +1,626 @@
+//! Command-line arguments.
+//!
+//! Options follow GNU conventions: short options can be bundled (`-rE`),
+//! a short option's argument can be attached (`-j4`) or follow as the next
+//! argument, long options can be abbreviated to any unambiguous prefix and
+//! take arguments as `--name=value` or `--name value`, options and operands
+//! can be mixed in any order, and `--` ends the options.
+
+use thiserror::Error;
+
+/// The options and operands grep was invoked with.
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    /// The patterns to search for, from `-e` options or else the first
+    /// operand.
+    pub patterns: Vec<String>,
+    /// Whether to ignore case (`-i`, `-S` or `--no-ignore-case`, the
+    /// last of which wins).
+    pub case: CaseMode,
+    /// Only match whole words (`-w`).
+    pub word_regexp: bool,
+    /// Only match whole lines (`-x`).
+    pub line_regexp: bool,
+    pub paths: Vec<String>,
+    /// Search directories recursively (`-r`, or `-R` to follow links).
+    pub recursive: bool,
+    pub follow_links: bool,
+    /// Visit files in a deterministic order (`--sort=path`).
+    pub sort: bool,
+    /// Search hidden files when recursing (`--hidden`).
+    pub hidden: bool,
+    /// Ignore `.gitignore` and `.ignore` files when recursing
+    /// (`--no-ignore`).
+    pub no_ignore: bool,
+    /// Select the lines that don't match (`-v`).
+    pub invert: bool,
+    /// Print the number of selected lines per file instead of the lines
+    /// (`-c`).
+    pub count: bool,
+    /// Prefix lines with their line number (`-n`).
+    pub line_number: bool,
+    /// Prefix lines with the column of their first match (`--column`).
+    pub column: bool,
+    /// Prefix lines with their byte offset (`-b`).
+    pub byte_offset: bool,
+    /// Print only the matched parts of lines (`-o`).
+    pub only_matching: bool,
+    /// When to color the output (`--color`).
+    pub color: ColorChoice,
+    /// Lines of leading context (`-B`), overriding `context`.
+    pub before_context: Option<usize>,
+    /// Lines of trailing context (`-A`), overriding `context`.
+    pub after_context: Option<usize>,
+    /// Lines of context on both sides (`-C` or `-NUM`).
+    pub context: Option<usize>,
+    /// The line between non-adjacent groups of context (`--`, changed by
+    /// `--group-separator` and removed by `--no-group-separator`).
+    pub group_separator: Option<String>,
+    /// The number of files to search at once (`-j N`); the number of CPUs
+    /// if not given.
+    pub threads: Option<usize>,
+    /// Print the help text instead of searching (`--help`).
+    pub help: bool,
+    /// Print the version instead of searching (`-V`).
+    pub version: bool,
+}
+
+/// When to color the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Never,
+    Always,
+    /// Only when writing to a terminal.
+    Auto,
+}
+
+/// Whether patterns match regardless of case.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseMode {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Ignore case unless the pattern contains an uppercase letter.
+    Smart,
+}
+
+/// A problem with the command line. Each message reads like GNU grep's.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ArgsError {
+    #[error("invalid option -- '{0}'")]
+    InvalidOption(char),
+    #[error("unrecognized option '{0}'")]
+    UnrecognizedOption(String),
+    #[error(
+        "option '{option}' is ambiguous; possibilities: {}",
+        possibilities(candidates)
+    )]
+    AmbiguousOption {
+        option: String,
+        candidates: Vec<&'static str>,
+    },
+    #[error("option requires an argument -- '{0}'")]
+    MissingShortArgument(char),
+    #[error("option '--{0}' requires an argument")]
+    MissingArgument(&'static str),
+    #[error("option '--{0}' doesn't allow an argument")]
+    UnexpectedArgument(&'static str),
+    #[error("invalid argument '{value}' for '{option}'")]
+    InvalidArgument { option: String, value: String },
+    #[error("{0}: invalid context length argument")]
+    InvalidContext(String),
+    #[error("no pattern given")]
+    NoPattern,
+}
+
+/// An option grep accepts.
+struct Opt {
+    short: Option<char>,
+    long: &'static str,
+    value: Value,
+}
+
+/// Whether an option takes an argument.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Value {
+    None,
+    Required,
+    /// Given only as `--name=value`; `--name` alone leaves it out.
+    Optional,
+}
+
+const OPTIONS: &[Opt] = &[
+    Opt {
+        short: Some('E'),
+        long: "extended-regexp",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('e'),
+        long: "regexp",
+        value: Value::Required,
+    },
+    Opt {
+        short: Some('i'),
+        long: "ignore-case",
+        value: Value::None,
+    },
+    Opt {
+        short: None,
+        long: "no-ignore-case",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('S'),
+        long: "smart-case",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('w'),
+        long: "word-regexp",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('x'),
+        long: "line-regexp",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('v'),
+        long: "invert-match",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('c'),
+        long: "count",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('n'),
+        long: "line-number",
+        value: Value::None,
+    },
+    Opt {
+        short: None,
+        long: "column",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('b'),
+        long: "byte-offset",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('o'),
+        long: "only-matching",
+        value: Value::None,
+    },
+    Opt {
+        short: None,
+        long: "color",
+        value: Value::Optional,
+    },
+    Opt {
+        short: Some('A'),
+        long: "after-context",
+        value: Value::Required,
+    },
+    Opt {
+        short: Some('B'),
+        long: "before-context",
+        value: Value::Required,
+    },
+    Opt {
+        short: Some('C'),
+        long: "context",
+        value: Value::Required,
+    },
+    Opt {
+        short: None,
+        long: "group-separator",
+        value: Value::Required,
+    },
+    Opt {
+        short: None,
+        long: "no-group-separator",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('r'),
+        long: "recursive",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('R'),
+        long: "dereference-recursive",
+        value: Value::None,
+    },
+    Opt {
+        short: None,
+        long: "sort",
+        value: Value::Required,
+    },
+    Opt {
+        short: None,
+        long: "hidden",
+        value: Value::None,
+    },
+    Opt {
+        short: None,
+        long: "no-ignore",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('j'),
+        long: "threads",
+        value: Value::Required,
+    },
+    Opt {
+        short: None,
+        long: "help",
+        value: Value::None,
+    },
+    Opt {
+        short: Some('V'),
+        long: "version",
+        value: Value::None,
+    },
+];
+
+/// Other names for long options, as `(alias, option)`.
+const ALIASES: &[(&str, &str)] = &[("colour", "color")];
+
+impl Args {
+    /// The synopsis printed with usage errors.
+    pub const USAGE: &'static str = "Usage: grep [OPTION]... PATTERNS [FILE]...";
+
+    /// The text printed by `--help`.
+    pub const HELP: &'static str = "\
+Usage: grep [OPTION]... PATTERNS [FILE]...
+Search for PATTERNS in each FILE.
+PATTERNS can contain multiple patterns separated by newlines.
+
+Pattern selection:
+  -E, --extended-regexp     PATTERNS are extended regular expressions
+  -e, --regexp=PATTERNS     use PATTERNS for matching
+  -i, --ignore-case         ignore case distinctions in patterns and data
+      --no-ignore-case      do not ignore case distinctions (default)
+  -S, --smart-case          ignore case unless PATTERNS contain uppercase
+  -w, --word-regexp         match only whole words
+  -x, --line-regexp         match only whole lines
+
+Miscellaneous:
+  -v, --invert-match        select non-matching lines
+  -V, --version             display version information and exit
+      --help                display this help text and exit
+
+Output control:
+  -b, --byte-offset         print the byte offset with output lines
+  -n, --line-number         print line number with output lines
+      --column              print the column of the first match
+  -o, --only-matching       show only nonempty parts of lines that match
+  -c, --count               print only a count of selected lines per FILE
+      --color[=WHEN],
+      --colour[=WHEN]       use markers to highlight the matching strings;
+                            WHEN is 'always', 'never', or 'auto'
+
+Context control:
+  -B, --before-context=NUM  print NUM lines of leading context
+  -A, --after-context=NUM   print NUM lines of trailing context
+  -C, --context=NUM         print NUM lines of output context
+  -NUM                      same as --context=NUM
+      --group-separator=SEP  print SEP on line between matches with context
+      --no-group-separator  do not print separator for matches with context
+
+File and directory selection:
+  -r, --recursive           search directories recursively
+  -R, --dereference-recursive  likewise, but follow all symlinks
+      --sort=path|none      search files in path order
+      --hidden              search hidden files and directories
+      --no-ignore           don't use .gitignore and .ignore files
+  -j, --threads=N           search N files at once
+
+When FILE is '-', read standard input. With no FILE, read '.' if
+recursive, '-' otherwise.
+Exit status is 0 if any line is selected, 1 otherwise;
+if any error occurs, the exit status is 2.
+";
+
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, ArgsError> {
+        let mut parsed = Args {
+            group_separator: Some("--".to_string()),
+            ..Args::default()
+        };
+        let mut operands = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                operands.extend(args.by_ref());
+            } else if let Some(long) = arg.strip_prefix("--") {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let opt = find_long(name)?;
+                let value = match (opt.value, value) {
+                    (Value::Required, None) => {
+                        Some(args.next().ok_or(ArgsError::MissingArgument(opt.long))?)
+                    }
+                    (Value::None, Some(_)) => return Err(ArgsError::UnexpectedArgument(opt.long)),
+                    (_, value) => value,
+                };
+                parsed.apply(opt, &format!("--{}", opt.long), value)?;
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                let mut rest = &arg[1..];
+                while let Some(c) = rest.chars().next() {
+                    rest = &rest[c.len_utf8()..];
+                    if c.is_ascii_digit() {
+                        // -NUM is short for -C NUM.
+                        let len = rest
+                            .find(|c: char| !c.is_ascii_digit())
+                            .unwrap_or(rest.len());
+                        let number = format!("{}{}", c, &rest[..len]);
+                        rest = &rest[len..];
+                        parsed.context = Some(context_length(number)?);
+                        continue;
+                    }
+                    let opt = OPTIONS
+                        .iter()
+                        .find(|opt| opt.short == Some(c))
+                        .ok_or(ArgsError::InvalidOption(c))?;
+                    if opt.value == Value::None {
+                        parsed.apply(opt, &format!("-{}", c), None)?;
+                        continue;
+                    }
+                    // The rest of the bundle, if any, is the argument.
+                    let value = match rest.is_empty() {
+                        true => args.next().ok_or(ArgsError::MissingShortArgument(c))?,
+                        false => rest.to_string(),
+                    };
+                    parsed.apply(opt, &format!("-{}", c), Some(value))?;
+                    break;
+                }
+            } else {
+                operands.push(arg);
+            }
+        }
+        if parsed.help || parsed.version {
+            return Ok(parsed);
+        }
+        let mut operands = operands.into_iter();
+        if parsed.patterns.is_empty() {
+            parsed
+                .patterns
+                .push(operands.next().ok_or(ArgsError::NoPattern)?);
+        }
+        parsed.paths = operands.collect();
+        Ok(parsed)
+    }
+
+    /// Applies one option. `name` is how it was written, for messages.
+    fn apply(&mut self, opt: &Opt, name: &str, value: Option<String>) -> Result<(), ArgsError> {
+        let invalid = |value: String| ArgsError::InvalidArgument {
+            option: name.to_string(),
+            value,
+        };
+        match opt.long {
+            "extended-regexp" => {}
+            "regexp" => self.patterns.extend(value),
+            "ignore-case" => self.case = CaseMode::Insensitive,
+            "no-ignore-case" => self.case = CaseMode::Sensitive,
+            "smart-case" => self.case = CaseMode::Smart,
+            "word-regexp" => self.word_regexp = true,
+            "line-regexp" => self.line_regexp = true,
+            "invert-match" => self.invert = true,
+            "count" => self.count = true,
+            "line-number" => self.line_number = true,
+            "column" => self.column = true,
+            "byte-offset" => self.byte_offset = true,
+            "only-matching" => self.only_matching = true,
+            "color" => {
+                self.color = match value.as_deref() {
+                    None | Some("auto" | "tty" | "if-tty") => ColorChoice::Auto,
+                    Some("always" | "yes" | "force") => ColorChoice::Always,
+                    Some("never" | "no" | "none") => ColorChoice::Never,
+                    Some(_) => return Err(invalid(value.unwrap_or_default())),
+                }
+            }
+            "after-context" => {
+                self.after_context = Some(context_length(value.unwrap_or_default())?)
+            }
+            "before-context" => {
+                self.before_context = Some(context_length(value.unwrap_or_default())?)
+            }
+            "context" => self.context = Some(context_length(value.unwrap_or_default())?),
+            "group-separator" => self.group_separator = value,
+            "no-group-separator" => self.group_separator = None,
+            "recursive" => self.recursive = true,
+            "dereference-recursive" => {
+                self.recursive = true;
+                self.follow_links = true;
+            }
+            "sort" => {
+                self.sort = match value.as_deref() {
+                    Some("path") => true,
+                    Some("none") => false,
+                    _ => return Err(invalid(value.unwrap_or_default())),
+                }
+            }
+            "hidden" => self.hidden = true,
+            "no-ignore" => self.no_ignore = true,
+            "threads" => {
+                let value = value.unwrap_or_default();
+                self.threads = match value.parse() {
+                    Ok(0) | Err(_) => return Err(invalid(value)),
+                    Ok(threads) => Some(threads),
+                };
+            }
+            "help" => self.help = true,
+            "version" => self.version = true,
+            _ => unreachable!("unhandled option --{}", opt.long),
+        }
+        Ok(())
+    }
+}
+
+fn context_length(value: String) -> Result<usize, ArgsError> {
+    value.parse().map_err(|_| ArgsError::InvalidContext(value))
+}
+
+/// Finds the long option `name` abbreviates. A prefix of several names
+/// is only ambiguous if they belong to different options, so `--colo`
+/// means `--color` even though `--colour` starts with it too.
+fn find_long(name: &str) -> Result<&'static Opt, ArgsError> {
+    let names = OPTIONS
+        .iter()
+        .map(|opt| (opt.long, opt.long))
+        .chain(ALIASES.iter().copied());
+    let lookup = |long: &str| OPTIONS.iter().find(|opt| opt.long == long).unwrap();
+    if let Some((_, long)) = names.clone().find(|&(alias, _)| alias == name) {
+        return Ok(lookup(long));
+    }
+    let mut candidates: Vec<&'static Opt> = Vec::new();
+    for (alias, long) in names {
+        let opt = lookup(long);
+        let new = !candidates.iter().any(|found| found.long == long);
+        if !name.is_empty() && alias.starts_with(name) && new {
+            candidates.push(opt);
+        }
+    }
+    match candidates.as_slice() {
+        [opt] => Ok(opt),
+        [] => Err(ArgsError::UnrecognizedOption(format!("--{}", name))),
+        _ => Err(ArgsError::AmbiguousOption {
+            option: format!("--{}", name),
+            candidates: candidates.iter().map(|opt| opt.long).collect(),
+        }),
+    }
+}
+
+fn possibilities(candidates: &[&str]) -> String {
+    let quoted: Vec<String> = candidates
+        .iter()
+        .map(|name| format!("'--{}'", name))
+        .collect();
+    quoted.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn short_options_bundle_and_take_attached_arguments() {
+        let args = parse(&["-nie", "PAT", "-j4", "file"]).unwrap();
+        assert!(args.line_number);
+        assert_eq!(args.case, CaseMode::Insensitive);
+        assert_eq!(args.patterns, ["PAT"]);
+        assert_eq!(args.threads, Some(4));
+        assert_eq!(args.paths, ["file"]);
+
+        // The rest of the bundle is the argument, wherever it starts.
+        let args = parse(&["-ePAT", "-cj", "2"]).unwrap();
+        assert_eq!(args.patterns, ["PAT"]);
+        assert!(args.count);
+        assert_eq!(args.threads, Some(2));
+        assert_eq!(
+            parse(&["x", "-j"]).unwrap_err(),
+            ArgsError::MissingShortArgument('j')
+        );
+        assert_eq!(
+            parse(&["-nq", "x"]).unwrap_err(),
+            ArgsError::InvalidOption('q')
+        );
+    }
+
+    #[test]
+    fn numbers_in_a_bundle_are_context() {
+        let args = parse(&["-n5", "x"]).unwrap();
+        assert!(args.line_number);
+        assert_eq!(args.context, Some(5));
+        let args = parse(&["-12v", "x"]).unwrap();
+        assert_eq!(args.context, Some(12));
+        assert!(args.invert);
+        assert_eq!(parse(&["-2", "-3", "x"]).unwrap().context, Some(3));
+    }
+
+    #[test]
+    fn double_dash_ends_the_options() {
+        let args = parse(&["-n", "--", "-v", "--count", "-"]).unwrap();
+        assert!(args.line_number);
+        assert!(!args.invert && !args.count);
+        assert_eq!(args.patterns, ["-v"]);
+        assert_eq!(args.paths, ["--count", "-"]);
+
+        // Before `--`, options and operands mix in any order.
+        let args = parse(&["pat", "a", "-c", "b"]).unwrap();
+        assert!(args.count);
+        assert_eq!(args.patterns, ["pat"]);
+        assert_eq!(args.paths, ["a", "b"]);
+    }
+
+    #[test]
+    fn every_regexp_option_adds_a_pattern() {
+        let args = parse(&["-e", "a", "--regexp=b", "-ec", "--regexp", "d", "file"]).unwrap();
+        assert_eq!(args.patterns, ["a", "b", "c", "d"]);
+        assert_eq!(args.paths, ["file"]);
+        assert_eq!(parse(&["-n"]).unwrap_err(), ArgsError::NoPattern);
+    }
+
+    #[test]
+    fn long_options_can_be_abbreviated() {
+        let args = parse(&["--line-n", "--colo=always", "--cou", "x"]).unwrap();
+        assert!(args.line_number && args.count);
+        assert_eq!(args.color, ColorChoice::Always);
+        // `--colo` also starts `--colour`, but that is the same option.
+        assert_eq!(parse(&["--colour", "x"]).unwrap().color, ColorChoice::Auto);
+        assert_eq!(
+            parse(&["--co", "x"]).unwrap_err(),
+            ArgsError::AmbiguousOption {
+                option: "--co".to_string(),
+                candidates: vec!["count", "column", "color", "context"],
+            }
+        );
+        assert_eq!(
+            parse(&["--colors", "x"]).unwrap_err(),
+            ArgsError::UnrecognizedOption("--colors".to_string())
+        );
+        assert_eq!(
+            parse(&["--count=3", "x"]).unwrap_err(),
+            ArgsError::UnexpectedArgument("count")
+        );
+        assert_eq!(
+            parse(&["x", "--context"]).unwrap_err(),
+            ArgsError::MissingArgument("context")
+        );
+    }
+
+    #[test]
+    fn an_exact_name_wins_over_longer_ones() {
+        let args = parse(&["--no-ignore", "x"]).unwrap();
+        assert!(args.no_ignore);
+        assert_eq!(args.case, CaseMode::Sensitive);
+
+        let args = parse(&["-i", "--no-ignore-case", "x"]).unwrap();
+        assert!(!args.no_ignore);
+        assert_eq!(args.case, CaseMode::Sensitive);
+
+        assert_eq!(
+            parse(&["--no-ig", "x"]).unwrap_err(),
+            ArgsError::AmbiguousOption {
+                option: "--no-ig".to_string(),
+                candidates: vec!["no-ignore-case", "no-ignore"],
+            }
+        );
+        assert_eq!(
+            parse(&["--no-ignore-c", "x"]).unwrap().case,
+            CaseMode::Sensitive
+        );
+    }
+}
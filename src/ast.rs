@@ -0,0 +1,156 @@
+//! Abstract syntax tree for extended regular expressions.
+
+/// A parsed pattern.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ast {
+    /// Matches the empty string, e.g. an empty branch in `a|`.
+    Empty,
+    /// A single literal character.
+    Literal(char),
+    /// `.`, any character except a newline.
+    Dot,
+    /// A bracket expression such as `[abc]`.
+    Class(Class),
+    /// A zero-width assertion such as `^`.
+    Assertion(Assertion),
+    /// A parenthesized sub-expression.
+    Group(Group),
+    /// A sequence of expressions matched one after another.
+    Concat(Vec<Ast>),
+    /// A set of branches separated by `|`, tried in order.
+    Alternation(Vec<Ast>),
+    /// An expression followed by a quantifier.
+    Repetition(Repetition),
+    /// `\1` through `\9`: the text last matched by a capturing group.
+    Backref(Backref),
+}
+
+impl Ast {
+    /// Whether this expression can match without consuming any input.
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            Ast::Empty | Ast::Assertion(_) | Ast::Backref(_) => true,
+            Ast::Literal(_) | Ast::Dot | Ast::Class(_) => false,
+            Ast::Group(group) => group.ast.is_nullable(),
+            Ast::Concat(items) => items.iter().all(Ast::is_nullable),
+            Ast::Alternation(branches) => branches.iter().any(Ast::is_nullable),
+            Ast::Repetition(rep) => rep.min == 0 || rep.ast.is_nullable(),
+        }
+    }
+
+    /// Whether this expression contains an uppercase literal character,
+    /// including the ends of ranges in classes. Named and shorthand
+    /// classes like `[:upper:]` and `\W` are not literals.
+    pub fn has_uppercase(&self) -> bool {
+        match self {
+            Ast::Literal(c) => c.is_uppercase(),
+            Ast::Class(class) => class.items.iter().any(|item| match *item {
+                ClassItem::Range(lo, hi) => lo.is_uppercase() || hi.is_uppercase(),
+                ClassItem::Perl { .. } | ClassItem::Posix(_) => false,
+            }),
+            Ast::Empty | Ast::Dot | Ast::Assertion(_) | Ast::Backref(_) => false,
+            Ast::Group(group) => group.ast.has_uppercase(),
+            Ast::Concat(items) | Ast::Alternation(items) => items.iter().any(Ast::has_uppercase),
+            Ast::Repetition(rep) => rep.ast.has_uppercase(),
+        }
+    }
+
+    /// The number of capturing groups in this expression.
+    pub fn captures(&self) -> usize {
+        match self {
+            Ast::Group(group) => 1 + group.ast.captures(),
+            Ast::Concat(items) | Ast::Alternation(items) => items.iter().map(Ast::captures).sum(),
+            Ast::Repetition(rep) => rep.ast.captures(),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Class {
+    /// Set for `[^...]`.
+    pub negated: bool,
+    /// Set when case is ignored: a character is then a member if any
+    /// character it folds together with is one of `items`.
+    pub fold: bool,
+    pub items: Vec<ClassItem>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClassItem {
+    /// An inclusive character range; single characters are `Range(c, c)`.
+    Range(char, char),
+    /// A shorthand class such as `\d`, or `\D` when `negated` is set.
+    Perl { class: PerlClass, negated: bool },
+    /// A named class such as `[:alpha:]`.
+    Posix(PosixClass),
+}
+
+/// The shorthand classes written as backslash escapes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PerlClass {
+    /// `\d`
+    Digit,
+    /// `\w`
+    Word,
+    /// `\s`
+    Space,
+}
+
+/// The named classes usable inside bracket expressions, e.g. `[[:alpha:]]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PosixClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    XDigit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Assertion {
+    /// `^`
+    StartLine,
+    /// `$`, which also matches just before a trailing newline.
+    EndLine,
+    /// `\b`
+    WordBoundary,
+    /// `\B`
+    NotWordBoundary,
+    /// Not preceded by a word character. No syntax produces it; it starts
+    /// the matches of [`RegexBuilder::whole_word`](crate::RegexBuilder::whole_word).
+    NoWordBefore,
+    /// Not followed by a word character; the counterpart of
+    /// [`NoWordBefore`](Assertion::NoWordBefore) at the end of a match.
+    NoWordAfter,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    /// 1-based capture index, numbered by opening parenthesis.
+    pub index: usize,
+    pub ast: Box<Ast>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backref {
+    /// The capture index of the group referred to.
+    pub index: usize,
+    /// Set when case is ignored, so `(a)\1` matches `aA`.
+    pub fold: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repetition {
+    pub min: u32,
+    /// `None` for an unbounded repetition such as `*`.
+    pub max: Option<u32>,
+    pub ast: Box<Ast>,
+}
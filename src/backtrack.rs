@@ -0,0 +1,267 @@
+//! A backtracking executor for compiled programs.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::compile::{is_look_match, Inst, Prog};
+use crate::{fold, utf8};
+
+enum Job {
+    Step { pc: usize, at: usize },
+    Restore { slot: usize, value: Option<usize> },
+}
+
+/// Searches for the leftmost match starting at or after `start`: among
+/// the matches starting there, the longest with `longest` set, and
+/// otherwise the first one found (leftmost-first).
+///
+/// On success the capture slots of the match are copied into `slots`.
+pub fn search(
+    prog: &Prog,
+    hay: &[u8],
+    start: usize,
+    longest: bool,
+    slots: &mut [Option<usize>],
+) -> bool {
+    let mut backtracker = Backtracker {
+        prog,
+        hay,
+        longest,
+        slots: vec![None; prog.slots],
+        best: None,
+        stack: Vec::new(),
+        state_slots: state_slots(prog),
+        visited: HashSet::new(),
+    };
+    let mut at = start;
+    loop {
+        if backtracker.run(at) {
+            let best = backtracker.best.as_deref().unwrap_or(&backtracker.slots);
+            let len = slots.len().min(best.len());
+            slots[..len].copy_from_slice(&best[..len]);
+            return true;
+        }
+        if at >= hay.len() {
+            return false;
+        }
+        at = utf8::next_boundary(hay, at);
+    }
+}
+
+/// A thread's instruction, position and `state_slots`.
+type State = (usize, usize, Box<[Option<usize>]>);
+
+struct Backtracker<'a> {
+    prog: &'a Prog,
+    hay: &'a [u8],
+    /// Try every way of matching and keep the longest, instead of stopping
+    /// at the first.
+    longest: bool,
+    slots: Vec<Option<usize>>,
+    /// The slots of the longest match found so far.
+    best: Option<Vec<Option<usize>>>,
+    stack: Vec<Job>,
+    /// The slots that, besides the position, decide how a thread can go
+    /// on: see [`state_slots`].
+    state_slots: Vec<usize>,
+    /// The branches already taken, with the values of `state_slots` then.
+    /// Taking one again can only find what was found the first time, so
+    /// each is explored once, which keeps the search polynomial.
+    visited: HashSet<State>,
+}
+
+impl<'a> Backtracker<'a> {
+    /// Tries to match starting exactly at `start`.
+    fn run(&mut self, start: usize) -> bool {
+        self.stack.clear();
+        self.stack.push(Job::Step { pc: 0, at: start });
+        while let Some(job) = self.stack.pop() {
+            match job {
+                Job::Restore { slot, value } => self.slots[slot] = value,
+                Job::Step { pc, at } => {
+                    if !self.step(pc, at) {
+                        continue;
+                    }
+                    if !self.longest {
+                        return true;
+                    }
+                    // Slot 1 holds where the match ends.
+                    let longer = match &self.best {
+                        Some(best) => self.slots[1] > best[1],
+                        None => true,
+                    };
+                    if longer {
+                        self.best = Some(self.slots.clone());
+                    }
+                    // Nothing can be longer than a match to the end.
+                    if self.slots[1] == Some(self.hay.len()) {
+                        return true;
+                    }
+                }
+            }
+        }
+        self.best.is_some()
+    }
+
+    /// Follows one thread until it fails or matches, pushing alternatives
+    /// onto the stack.
+    fn step(&mut self, mut pc: usize, mut at: usize) -> bool {
+        loop {
+            match &self.prog.insts[pc] {
+                inst @ (Inst::Char(_) | Inst::Any | Inst::Class(_)) => {
+                    match utf8::decode(self.hay, at) {
+                        Some((c, len)) if inst.matches_char(c) => {
+                            pc += 1;
+                            at += len;
+                        }
+                        _ => return false,
+                    }
+                }
+                Inst::Assert(assertion) => {
+                    if !is_look_match(*assertion, self.hay, at, self.prog.unicode) {
+                        return false;
+                    }
+                    pc += 1;
+                }
+                Inst::Split(first, second) => {
+                    if !self.visit(pc, at) {
+                        return false;
+                    }
+                    self.stack.push(Job::Step { pc: *second, at });
+                    pc = *first;
+                }
+                Inst::Jmp(target) => pc = *target,
+                Inst::Save(slot) | Inst::Mark(slot) => {
+                    let value = self.slots[*slot];
+                    self.stack.push(Job::Restore { slot: *slot, value });
+                    self.slots[*slot] = Some(at);
+                    pc += 1;
+                }
+                Inst::Backref(backref) => {
+                    let index = backref.index;
+                    let (Some(start), Some(end)) =
+                        (self.slots[2 * index], self.slots[2 * index + 1])
+                    else {
+                        return false;
+                    };
+                    let Some(len) = self.match_backref(start..end, at, backref.fold) else {
+                        return false;
+                    };
+                    pc += 1;
+                    at += len;
+                }
+                Inst::Progress { slot, exit } => {
+                    if self.slots[*slot] == Some(at) {
+                        pc = *exit;
+                    } else {
+                        pc += 1;
+                    }
+                }
+                Inst::Match => return true,
+            }
+        }
+    }
+
+    /// Records that the thread at `pc` and `at` branches, returning false
+    /// if one in the same state already did.
+    ///
+    /// No state is forgotten between starting positions: a search only
+    /// moves on to the next one if nothing matched, and whether a state
+    /// leads to a match does not depend on where the match started.
+    fn visit(&mut self, pc: usize, at: usize) -> bool {
+        let slots = self
+            .state_slots
+            .iter()
+            .map(|&slot| self.slots[slot])
+            .collect();
+        self.visited.insert((pc, at, slots))
+    }
+
+    /// Matches the text in `captured` again at `at`, ignoring case with
+    /// `fold` set, and returns the length of the text matched. Folded
+    /// characters can differ in length, as `s` and `ſ` do.
+    fn match_backref(&self, captured: Range<usize>, at: usize, fold: bool) -> Option<usize> {
+        let len = captured.len();
+        if !fold {
+            return (self.hay.get(at..at + len)? == &self.hay[captured]).then_some(len);
+        }
+        let mut end = at;
+        let mut from = captured.start;
+        while from < captured.end {
+            let (expected, expected_len) = utf8::decode(self.hay, from)?;
+            let (c, c_len) = utf8::decode(self.hay, end)?;
+            if !fold::equivalent(expected, c) {
+                return None;
+            }
+            from += expected_len;
+            end += c_len;
+        }
+        Some(end - at)
+    }
+}
+
+/// The slots a thread's future depends on: those of the groups a
+/// backreference refers to, and the loop guards' scratch slots.
+fn state_slots(prog: &Prog) -> Vec<usize> {
+    let mut slots: Vec<usize> = prog
+        .insts
+        .iter()
+        .filter_map(|inst| match inst {
+            Inst::Backref(backref) => Some(backref.index),
+            _ => None,
+        })
+        .flat_map(|index| [2 * index, 2 * index + 1])
+        .collect();
+    slots.sort_unstable();
+    slots.dedup();
+    slots.extend(2 * prog.captures..prog.slots);
+    slots
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::compile::{compile, Config};
+    use crate::parse::parse;
+
+    fn find(pattern: &str, hay: &str, longest: bool) -> Option<(usize, usize)> {
+        let config = Config {
+            unicode: true,
+            size_limit: 1 << 20,
+        };
+        let prog = compile(&parse(pattern).unwrap(), config).unwrap();
+        let mut slots = [None, None];
+        search(&prog, hay.as_bytes(), 0, longest, &mut slots).then(|| match slots {
+            [Some(start), Some(end)] => (start, end),
+            _ => unreachable!("a match has a span"),
+        })
+    }
+
+    #[test]
+    fn longest_matches_take_polynomial_time() {
+        let started = Instant::now();
+        let nested = "(((((b){0,2}){0,2}){0,2})+)x\\1";
+        assert_eq!(find(nested, "bbbbbx", true), Some((5, 6)));
+        assert_eq!(
+            find(nested, &format!("{}x", "b".repeat(40)), true),
+            Some((40, 41))
+        );
+        let starred = "(a*)*[bc]\\1";
+        assert_eq!(find(starred, &"a".repeat(200), true), None);
+        assert_eq!(find(starred, &"a".repeat(200), false), None);
+        assert_eq!(
+            find(starred, &format!("{}b", "a".repeat(200)), true),
+            Some((0, 201))
+        );
+        // Each of these took seconds to hours before states were memoized.
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn longest_stops_at_a_match_to_the_end() {
+        assert_eq!(find("(a|ab)(c|bcd)\\2?", "abcd", true), Some((0, 4)));
+        assert_eq!(find("(a*)b*\\1", "aabaa", true), Some((0, 5)));
+    }
+}
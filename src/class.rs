@@ -0,0 +1,178 @@
+//! Compiled character classes.
+
+use crate::ast::{Class, ClassItem, PerlClass, PosixClass};
+use crate::fold;
+
+/// A bracket expression lowered for matching.
+#[derive(Clone, Debug)]
+pub struct CharClass {
+    negated: bool,
+    /// Whether case is ignored.
+    fold: bool,
+    /// Whether shorthand classes use Unicode rather than ASCII semantics.
+    unicode: bool,
+    items: Vec<ClassItem>,
+}
+
+impl CharClass {
+    pub fn new(class: &Class, unicode: bool) -> CharClass {
+        CharClass {
+            negated: class.negated,
+            fold: class.fold,
+            unicode,
+            items: class.items.clone(),
+        }
+    }
+
+    pub fn matches(&self, c: char) -> bool {
+        let found = self.contains(c)
+            || (self.fold && fold::orbit(c)[1..].iter().any(|&c| self.contains(c)));
+        found != self.negated
+    }
+
+    /// Whether one of the items matches `c` as it is.
+    fn contains(&self, c: char) -> bool {
+        self.items.iter().any(|item| match *item {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Perl { class, negated } => perl_matches(class, c, self.unicode) != negated,
+            ClassItem::Posix(class) => posix_matches(class, c, self.unicode),
+        })
+    }
+}
+
+fn perl_matches(class: PerlClass, c: char, unicode: bool) -> bool {
+    match (class, unicode) {
+        (PerlClass::Digit, false) => c.is_ascii_digit(),
+        (PerlClass::Digit, true) => is_decimal_digit(c),
+        (PerlClass::Word, _) => is_word_char(c, unicode),
+        // ASCII whitespace plus the vertical tab, as in Perl.
+        (PerlClass::Space, false) => c.is_ascii_whitespace() || c == '\x0B',
+        (PerlClass::Space, true) => c.is_whitespace(),
+    }
+}
+
+fn posix_matches(class: PosixClass, c: char, unicode: bool) -> bool {
+    if !unicode || c.is_ascii() {
+        return match class {
+            PosixClass::Alnum => c.is_ascii_alphanumeric(),
+            PosixClass::Alpha => c.is_ascii_alphabetic(),
+            PosixClass::Blank => c == ' ' || c == '\t',
+            PosixClass::Cntrl => c.is_ascii_control(),
+            PosixClass::Digit => c.is_ascii_digit(),
+            PosixClass::Graph => c.is_ascii_graphic(),
+            PosixClass::Lower => c.is_ascii_lowercase(),
+            PosixClass::Print => c.is_ascii_graphic() || c == ' ',
+            PosixClass::Punct => c.is_ascii_punctuation(),
+            PosixClass::Space => c.is_ascii_whitespace() || c == '\x0B',
+            PosixClass::Upper => c.is_ascii_uppercase(),
+            PosixClass::XDigit => c.is_ascii_hexdigit(),
+        };
+    }
+    // Outside ASCII, approximate the C library's wide character classes.
+    match class {
+        PosixClass::Alnum => c.is_alphanumeric(),
+        PosixClass::Alpha => c.is_alphabetic(),
+        PosixClass::Blank => {
+            c.is_whitespace() && c != '\u{85}' && c != '\u{2028}' && c != '\u{2029}'
+        }
+        PosixClass::Cntrl => c.is_control(),
+        PosixClass::Digit | PosixClass::XDigit => false,
+        PosixClass::Graph | PosixClass::Print => !c.is_control() && !c.is_whitespace(),
+        PosixClass::Lower => c.is_lowercase(),
+        PosixClass::Punct => !c.is_alphanumeric() && !c.is_control() && !c.is_whitespace(),
+        PosixClass::Space => c.is_whitespace(),
+        PosixClass::Upper => c.is_uppercase(),
+    }
+}
+
+/// The ranges of decimal digits, general category Nd, sorted. Unlike
+/// `char::is_numeric`, this leaves out numerals such as `½`, `²` and `Ⅷ`.
+const DECIMAL_DIGITS: &[(char, char)] = &[
+    ('\u{30}', '\u{39}'),
+    ('\u{660}', '\u{669}'),
+    ('\u{6F0}', '\u{6F9}'),
+    ('\u{7C0}', '\u{7C9}'),
+    ('\u{966}', '\u{96F}'),
+    ('\u{9E6}', '\u{9EF}'),
+    ('\u{A66}', '\u{A6F}'),
+    ('\u{AE6}', '\u{AEF}'),
+    ('\u{B66}', '\u{B6F}'),
+    ('\u{BE6}', '\u{BEF}'),
+    ('\u{C66}', '\u{C6F}'),
+    ('\u{CE6}', '\u{CEF}'),
+    ('\u{D66}', '\u{D6F}'),
+    ('\u{DE6}', '\u{DEF}'),
+    ('\u{E50}', '\u{E59}'),
+    ('\u{ED0}', '\u{ED9}'),
+    ('\u{F20}', '\u{F29}'),
+    ('\u{1040}', '\u{1049}'),
+    ('\u{1090}', '\u{1099}'),
+    ('\u{17E0}', '\u{17E9}'),
+    ('\u{1810}', '\u{1819}'),
+    ('\u{1946}', '\u{194F}'),
+    ('\u{19D0}', '\u{19D9}'),
+    ('\u{1A80}', '\u{1A89}'),
+    ('\u{1A90}', '\u{1A99}'),
+    ('\u{1B50}', '\u{1B59}'),
+    ('\u{1BB0}', '\u{1BB9}'),
+    ('\u{1C40}', '\u{1C49}'),
+    ('\u{1C50}', '\u{1C59}'),
+    ('\u{A620}', '\u{A629}'),
+    ('\u{A8D0}', '\u{A8D9}'),
+    ('\u{A900}', '\u{A909}'),
+    ('\u{A9D0}', '\u{A9D9}'),
+    ('\u{A9F0}', '\u{A9F9}'),
+    ('\u{AA50}', '\u{AA59}'),
+    ('\u{ABF0}', '\u{ABF9}'),
+    ('\u{FF10}', '\u{FF19}'),
+    ('\u{104A0}', '\u{104A9}'),
+    ('\u{10D30}', '\u{10D39}'),
+    ('\u{10D40}', '\u{10D49}'),
+    ('\u{11066}', '\u{1106F}'),
+    ('\u{110F0}', '\u{110F9}'),
+    ('\u{11136}', '\u{1113F}'),
+    ('\u{111D0}', '\u{111D9}'),
+    ('\u{112F0}', '\u{112F9}'),
+    ('\u{11450}', '\u{11459}'),
+    ('\u{114D0}', '\u{114D9}'),
+    ('\u{11650}', '\u{11659}'),
+    ('\u{116C0}', '\u{116C9}'),
+    ('\u{116D0}', '\u{116E3}'),
+    ('\u{11730}', '\u{11739}'),
+    ('\u{118E0}', '\u{118E9}'),
+    ('\u{11950}', '\u{11959}'),
+    ('\u{11BF0}', '\u{11BF9}'),
+    ('\u{11C50}', '\u{11C59}'),
+    ('\u{11D50}', '\u{11D59}'),
+    ('\u{11DA0}', '\u{11DA9}'),
+    ('\u{11DE0}', '\u{11DE9}'),
+    ('\u{11F50}', '\u{11F59}'),
+    ('\u{16130}', '\u{16139}'),
+    ('\u{16A60}', '\u{16A69}'),
+    ('\u{16AC0}', '\u{16AC9}'),
+    ('\u{16B50}', '\u{16B59}'),
+    ('\u{16D70}', '\u{16D79}'),
+    ('\u{1CCF0}', '\u{1CCF9}'),
+    ('\u{1D7CE}', '\u{1D7FF}'),
+    ('\u{1E140}', '\u{1E149}'),
+    ('\u{1E2F0}', '\u{1E2F9}'),
+    ('\u{1E4F0}', '\u{1E4F9}'),
+    ('\u{1E5F1}', '\u{1E5FA}'),
+    ('\u{1E950}', '\u{1E959}'),
+    ('\u{1FBF0}', '\u{1FBF9}'),
+];
+
+/// Whether `c` is a Unicode decimal digit, as matched by `\d`.
+fn is_decimal_digit(c: char) -> bool {
+    let i = DECIMAL_DIGITS.partition_point(|&(_, hi)| hi < c);
+    DECIMAL_DIGITS.get(i).is_some_and(|&(lo, _)| lo <= c)
+}
+
+/// Whether `c` is matched by `\w`.
+pub fn is_word_char(c: char, unicode: bool) -> bool {
+    if unicode {
+        c.is_alphanumeric() || c == '_'
+    } else {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+}
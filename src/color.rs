@@ -0,0 +1,132 @@
+//! ANSI colors for output, configured like GNU grep's `GREP_COLORS`.
+
+use std::io::{self, Write};
+
+/// The SGR sequences used for each part of the output. An empty sequence
+/// leaves that part uncolored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Colors {
+    /// Matched text (`ms=`).
+    pub matched: String,
+    /// File names (`fn=`).
+    pub filename: String,
+    /// Line numbers and columns (`ln=`).
+    pub line_number: String,
+    /// Byte offsets (`bn=`).
+    pub byte_offset: String,
+    /// The separators between fields (`se=`).
+    pub separator: String,
+    /// Follow each sequence with "erase to end of line", so that a
+    /// background color doesn't spill past the text when the terminal
+    /// scrolls. `ne` turns it off.
+    pub erase: bool,
+}
+
+impl Default for Colors {
+    /// GNU grep's defaults.
+    fn default() -> Colors {
+        Colors {
+            matched: "01;31".to_string(),
+            filename: "35".to_string(),
+            line_number: "32".to_string(),
+            byte_offset: "32".to_string(),
+            separator: "36".to_string(),
+            erase: true,
+        }
+    }
+}
+
+impl Colors {
+    /// Applies a `GREP_COLORS` value such as `ms=01;32:fn=34:ne` to the
+    /// defaults. `mt=` sets the match color too. Capabilities that are
+    /// unknown, or whose value isn't a list of numbers, are ignored.
+    pub fn parse(spec: &str) -> Colors {
+        let mut colors = Colors::default();
+        for capability in spec.split(':') {
+            let (name, value) = match capability.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (capability, None),
+            };
+            let valid = |value: &str| value.bytes().all(|b| b.is_ascii_digit() || b == b';');
+            let field = match name {
+                "ms" | "mt" => &mut colors.matched,
+                "fn" => &mut colors.filename,
+                "ln" => &mut colors.line_number,
+                "bn" => &mut colors.byte_offset,
+                "se" => &mut colors.separator,
+                "ne" if value.is_none() => {
+                    colors.erase = false;
+                    continue;
+                }
+                _ => continue,
+            };
+            match value {
+                Some(value) if valid(value) => *field = value.to_string(),
+                _ => {}
+            }
+        }
+        colors
+    }
+
+    /// Writes `text` in the color `sgr`, one of this struct's fields.
+    pub fn paint<W: Write>(&self, out: &mut W, sgr: &str, text: &[u8]) -> io::Result<()> {
+        if sgr.is_empty() {
+            return out.write_all(text);
+        }
+        let erase = if self.erase { "\x1b[K" } else { "" };
+        write!(out, "\x1b[{}m{}", sgr, erase)?;
+        out.write_all(text)?;
+        write!(out, "\x1b[m{}", erase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_overrides_the_defaults() {
+        let colors = Colors::parse("ms=01;32:fn=:ne");
+        assert_eq!(
+            colors,
+            Colors {
+                matched: "01;32".to_string(),
+                filename: String::new(),
+                erase: false,
+                ..Colors::default()
+            }
+        );
+        assert_eq!(Colors::parse("mt=4").matched, "4");
+        assert_eq!(Colors::parse(""), Colors::default());
+    }
+
+    #[test]
+    fn parse_ignores_what_it_does_not_understand() {
+        // Values that aren't numbers, unknown capabilities, a missing value
+        // and `ne` with a value.
+        for spec in [
+            "ms=red", "ms=1m;", "xx=1:zz", "ln", "se=3\x1b", "ne=1", "::",
+        ] {
+            assert_eq!(Colors::parse(spec), Colors::default(), "{:?}", spec);
+        }
+        let colors = Colors::parse("ms=bad:ln=33:ms");
+        assert_eq!(colors.matched, "01;31");
+        assert_eq!(colors.line_number, "33");
+    }
+
+    #[test]
+    fn paint_erases_unless_told_not_to() {
+        let mut out = Vec::new();
+        let colors = Colors::default();
+        colors.paint(&mut out, &colors.matched, b"x").unwrap();
+        assert_eq!(out, b"\x1b[01;31m\x1b[Kx\x1b[m\x1b[K");
+
+        let colors = Colors::parse("ne:fn=");
+        out.clear();
+        colors.paint(&mut out, &colors.matched, b"x").unwrap();
+        assert_eq!(out, b"\x1b[01;31mx\x1b[m");
+        out.clear();
+        colors.paint(&mut out, &colors.filename, b"x").unwrap();
+        assert_eq!(out, b"x");
+    }
+}
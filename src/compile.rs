@@ -0,0 +1,283 @@
+//! Lowering of an [`Ast`] into a program of NFA instructions.
+
+use std::mem;
+
+use crate::ast::{Assertion, Ast, Backref};
+use crate::class::{is_word_char, CharClass};
+use crate::error::PatternError;
+use crate::utf8;
+
+/// A compiled pattern. Execution starts at instruction 0.
+///
+/// Slots `2 * i` and `2 * i + 1` hold the start and end of capture group
+/// `i`, with group 0 spanning the whole match. The loop guards' scratch
+/// slots follow the capture slots.
+#[derive(Clone, Debug)]
+pub struct Prog {
+    pub insts: Vec<Inst>,
+    /// Total number of slots, captures included.
+    pub slots: usize,
+    /// Number of capture groups, including the implicit group 0.
+    pub captures: usize,
+    /// Whether the program contains a backreference, which only the
+    /// backtracker can execute.
+    pub has_backrefs: bool,
+    /// Whether `\b` and `\B` use Unicode word characters.
+    pub unicode: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum Inst {
+    Char(char),
+    /// Any character except a newline.
+    Any,
+    Class(CharClass),
+    Assert(Assertion),
+    /// Try both branches, preferring the first.
+    Split(usize, usize),
+    Jmp(usize),
+    /// Record the current position in a capture slot.
+    Save(usize),
+    /// Match the text captured by a group again.
+    Backref(Backref),
+    /// Record the current position in a slot; paired with `Progress`.
+    Mark(usize),
+    /// Leave the loop for `exit` unless input was consumed since the
+    /// matching `Mark`. This lets a loop over an expression that can match
+    /// empty take one empty iteration, as POSIX requires for captures, but
+    /// keeps it from spinning forever.
+    Progress {
+        slot: usize,
+        exit: usize,
+    },
+    Match,
+}
+
+impl Inst {
+    /// Whether this instruction consumes `c`. Always false for instructions
+    /// that do not consume input.
+    pub fn matches_char(&self, c: char) -> bool {
+        match self {
+            Inst::Char(expected) => c == *expected,
+            Inst::Any => c != '\n',
+            Inst::Class(class) => class.matches(c),
+            _ => false,
+        }
+    }
+}
+
+/// Options that change how an [`Ast`] is lowered.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Use Unicode semantics for `\d`, `\w` and `\s`.
+    pub unicode: bool,
+    /// Approximate upper bound, in bytes, on the size of the program.
+    pub size_limit: usize,
+}
+
+pub fn compile(ast: &Ast, config: Config) -> Result<Prog, PatternError> {
+    let captures = ast.captures() + 1;
+    let mut compiler = Compiler {
+        config,
+        insts: Vec::new(),
+        slots: 2 * captures,
+        has_backrefs: false,
+    };
+    compiler.push(Inst::Save(0));
+    compiler.compile(ast)?;
+    compiler.push(Inst::Save(1));
+    compiler.push(Inst::Match);
+    Ok(Prog {
+        insts: compiler.insts,
+        slots: compiler.slots,
+        captures,
+        has_backrefs: compiler.has_backrefs,
+        unicode: config.unicode,
+    })
+}
+
+/// Whether a zero-width assertion holds at byte offset `at`.
+pub fn is_look_match(assertion: Assertion, hay: &[u8], at: usize, unicode: bool) -> bool {
+    let word_before = || utf8::decode_last(hay, at).is_some_and(|(c, _)| is_word_char(c, unicode));
+    let word_after = || utf8::decode(hay, at).is_some_and(|(c, _)| is_word_char(c, unicode));
+    match assertion {
+        Assertion::StartLine => at == 0,
+        Assertion::EndLine => at == hay.len() || &hay[at..] == b"\n",
+        Assertion::WordBoundary => word_before() != word_after(),
+        Assertion::NotWordBoundary => word_before() == word_after(),
+        Assertion::NoWordBefore => !word_before(),
+        Assertion::NoWordAfter => !word_after(),
+    }
+}
+
+struct Compiler {
+    config: Config,
+    insts: Vec<Inst>,
+    slots: usize,
+    has_backrefs: bool,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    fn compile(&mut self, ast: &Ast) -> Result<(), PatternError> {
+        match ast {
+            Ast::Empty => {}
+            Ast::Literal(c) => {
+                self.push(Inst::Char(*c));
+            }
+            Ast::Dot => {
+                self.push(Inst::Any);
+            }
+            Ast::Class(class) => {
+                self.push(Inst::Class(CharClass::new(class, self.config.unicode)));
+            }
+            Ast::Assertion(assertion) => {
+                self.push(Inst::Assert(*assertion));
+            }
+            Ast::Group(group) => {
+                self.push(Inst::Save(2 * group.index));
+                self.compile(&group.ast)?;
+                self.push(Inst::Save(2 * group.index + 1));
+            }
+            Ast::Backref(backref) => {
+                self.has_backrefs = true;
+                self.push(Inst::Backref(*backref));
+            }
+            Ast::Concat(items) => {
+                for item in items {
+                    self.compile(item)?;
+                }
+            }
+            Ast::Alternation(branches) => self.compile_alternation(branches)?,
+            Ast::Repetition(rep) => self.compile_repetition(&rep.ast, rep.min, rep.max)?,
+        }
+        // Bounded repetition copies its operand, so `(a{1000}){1000}` would
+        // otherwise need a million instructions. Checking after every node
+        // stops such patterns as soon as they cross the limit.
+        if self.insts.len() * mem::size_of::<Inst>() > self.config.size_limit {
+            return Err(PatternError::TooBig {
+                limit: self.config.size_limit,
+            });
+        }
+        Ok(())
+    }
+
+    fn compile_alternation(&mut self, branches: &[Ast]) -> Result<(), PatternError> {
+        let mut jumps = Vec::new();
+        let (last, rest) = branches.split_last().unwrap();
+        for branch in rest {
+            let split = self.push(Inst::Split(0, 0));
+            self.compile(branch)?;
+            jumps.push(self.push(Inst::Jmp(0)));
+            self.insts[split] = Inst::Split(split + 1, self.insts.len());
+        }
+        self.compile(last)?;
+        let end = self.insts.len();
+        for jump in jumps {
+            self.insts[jump] = Inst::Jmp(end);
+        }
+        Ok(())
+    }
+
+    fn compile_repetition(
+        &mut self,
+        ast: &Ast,
+        min: u32,
+        max: Option<u32>,
+    ) -> Result<(), PatternError> {
+        // Repeating nothing is nothing, however many times, and the size
+        // check would never stop `(?i:){4000000000}` from trying.
+        if compiles_to_nothing(ast) {
+            return Ok(());
+        }
+        for _ in 0..min {
+            self.compile(ast)?;
+        }
+        match max {
+            None => {
+                let split = self.push(Inst::Split(0, 0));
+                if ast.is_nullable() {
+                    let slot = self.slots;
+                    self.slots += 1;
+                    self.push(Inst::Mark(slot));
+                    self.compile(ast)?;
+                    self.push(Inst::Progress { slot, exit: 0 });
+                } else {
+                    self.compile(ast)?;
+                }
+                self.push(Inst::Jmp(split));
+                let exit = self.insts.len();
+                self.insts[split] = Inst::Split(split + 1, exit);
+                if let Inst::Progress { slot, .. } = self.insts[exit - 2] {
+                    self.insts[exit - 2] = Inst::Progress { slot, exit };
+                }
+            }
+            Some(max) => {
+                // e{2,4} becomes ee(e(e)?)?; every optional copy can skip
+                // straight to the end.
+                let mut splits = Vec::new();
+                for _ in min..max {
+                    splits.push(self.push(Inst::Split(0, 0)));
+                    self.compile(ast)?;
+                }
+                let end = self.insts.len();
+                for split in splits {
+                    self.insts[split] = Inst::Split(split + 1, end);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `ast` compiles to no instructions at all, like the empty flag
+/// group in `(?i:)*`.
+fn compiles_to_nothing(ast: &Ast) -> bool {
+    match ast {
+        Ast::Empty => true,
+        Ast::Concat(items) => items.iter().all(compiles_to_nothing),
+        Ast::Repetition(rep) => compiles_to_nothing(&rep.ast),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    fn compile_with_limit(pattern: &str, size_limit: usize) -> Result<Prog, PatternError> {
+        let config = Config {
+            unicode: true,
+            size_limit,
+        };
+        compile(&parse(pattern).unwrap(), config)
+    }
+
+    #[test]
+    fn nested_counts_hit_the_size_limit() {
+        let limit = 1 << 20;
+        assert_eq!(
+            compile_with_limit("(a{1000}){1000}", limit).unwrap_err(),
+            PatternError::TooBig { limit }
+        );
+        assert!(compile_with_limit("(a{100}){100}", limit).is_ok());
+    }
+
+    #[test]
+    fn repeating_an_empty_group_compiles_to_nothing() {
+        for pattern in [
+            "(?i:){4000000000}",
+            "(?-i:)*{4000000000}",
+            "(?i:(?i:)(?-i:)){,4000000000}",
+        ] {
+            let prog = compile_with_limit(pattern, 1 << 20).unwrap();
+            // Save(0), Save(1) and Match.
+            assert_eq!(prog.insts.len(), 3, "{:?}", pattern);
+        }
+    }
+}
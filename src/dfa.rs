@@ -0,0 +1,361 @@
+//! A lazily built DFA for answering whether a line matches at all.
+//!
+//! DFA states are sets of NFA instructions, created the first time they are
+//! reached and memoized together with their transitions, so that after a
+//! warm-up each character costs a single table lookup. The cache has a
+//! memory budget; when it fills up it is cleared and rebuilt on demand.
+//! Searches that keep clearing it without making progress give up and let
+//! the caller fall back to the Pike VM.
+//!
+//! Assertions are resolved while computing a transition, once the next
+//! character is known. Each state therefore remembers whether it sits at
+//! the start of the haystack and whether the previous character was a word
+//! character.
+
+use std::collections::HashMap;
+use std::mem;
+
+use crate::ast::Assertion;
+use crate::class::is_word_char;
+use crate::compile::{Inst, Prog};
+use crate::sparse::SparseSet;
+use crate::utf8;
+
+type StateId = u32;
+
+/// Transition not computed yet.
+const UNKNOWN: StateId = u32::MAX;
+/// No match is possible from here on.
+const DEAD: StateId = u32::MAX - 1;
+/// A match has been found.
+const MATCH: StateId = u32::MAX - 2;
+
+/// Number of cache clears tolerated in one search before its efficiency is
+/// checked.
+const MIN_CLEARS: usize = 3;
+/// Searches that give up this many times disable the DFA for good.
+const MAX_FAILURES: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    /// Sorted instructions to resume from, before following epsilons.
+    core: Box<[usize]>,
+    /// At the start of the haystack.
+    start: bool,
+    /// The previous character was a word character.
+    word: bool,
+}
+
+#[derive(Clone, Debug)]
+struct State {
+    key: Key,
+    ascii: Box<[StateId; 128]>,
+    other: HashMap<char, StateId>,
+}
+
+/// The memoized states and transitions for one program.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    states: Vec<State>,
+    map: HashMap<Key, StateId>,
+    start: Option<StateId>,
+    /// Approximate bytes used by `states` and `map`.
+    memory: usize,
+    limit: usize,
+    failures: usize,
+    set: SparseSet,
+    stack: Vec<usize>,
+}
+
+impl Cache {
+    pub fn new(prog: &Prog, limit: usize) -> Cache {
+        Cache {
+            states: Vec::new(),
+            map: HashMap::new(),
+            start: None,
+            memory: 0,
+            limit,
+            failures: 0,
+            set: SparseSet::new(prog.insts.len()),
+            stack: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.states.clear();
+        self.map.clear();
+        self.start = None;
+        self.memory = 0;
+    }
+
+    /// Interns `key`, clearing the cache first if it is full. Returns the
+    /// state and whether the cache was cleared.
+    fn add_state(&mut self, key: Key) -> (StateId, bool) {
+        if let Some(&id) = self.map.get(&key) {
+            return (id, false);
+        }
+        let cost = mem::size_of::<State>()
+            + mem::size_of::<[StateId; 128]>()
+            + 2 * key.core.len() * mem::size_of::<usize>();
+        let cleared = self.memory + cost > self.limit;
+        if cleared {
+            self.clear();
+        }
+        let id = self.states.len() as StateId;
+        self.states.push(State {
+            key: key.clone(),
+            ascii: Box::new([UNKNOWN; 128]),
+            other: HashMap::new(),
+        });
+        self.map.insert(key, id);
+        self.memory += cost;
+        (id, cleared)
+    }
+}
+
+/// Reports whether the program matches anywhere in `hay` at or after
+/// `start`, or `None` if the DFA gave up and the caller should use another
+/// engine.
+pub fn is_match(prog: &Prog, cache: &mut Cache, hay: &[u8], start: usize) -> Option<bool> {
+    if prog.has_backrefs || cache.failures >= MAX_FAILURES {
+        return None;
+    }
+    let anchored = is_anchored(prog);
+    let mut state = match cache.start {
+        Some(id) if start == 0 => id,
+        _ => {
+            let key = Key {
+                core: Box::new([0]),
+                start: start == 0,
+                word: utf8::decode_last(hay, start)
+                    .is_some_and(|(c, _)| is_word_char(c, prog.unicode)),
+            };
+            let (id, _) = cache.add_state(key);
+            if start == 0 {
+                cache.start = Some(id);
+            }
+            id
+        }
+    };
+    let mut clears = 0;
+    let mut created = 0;
+    let mut at = start;
+    while let Some((c, len)) = utf8::decode(hay, at) {
+        // `$` also matches before a final newline, so the transition on
+        // that newline differs from the memoized one and is not stored.
+        let eol = c == '\n' && at + len == hay.len();
+        let cached = match (eol, c.is_ascii()) {
+            (true, _) => UNKNOWN,
+            (false, true) => cache.states[state as usize].ascii[c as usize],
+            (false, false) => *cache.states[state as usize]
+                .other
+                .get(&c)
+                .unwrap_or(&UNKNOWN),
+        };
+        let next = if cached != UNKNOWN {
+            cached
+        } else {
+            let next = match step(prog, cache, state, Some(c), eol, anchored) {
+                Step::Match => MATCH,
+                Step::Dead => DEAD,
+                Step::Next(key) => {
+                    let (id, cleared) = cache.add_state(key);
+                    created += 1;
+                    if cleared {
+                        clears += 1;
+                        if clears >= MIN_CLEARS && at - start < 10 * created {
+                            cache.failures += 1;
+                            return None;
+                        }
+                        // `state` no longer exists, so there is nothing
+                        // to record the transition on.
+                        state = id;
+                        at += len;
+                        continue;
+                    }
+                    id
+                }
+            };
+            if !eol {
+                let from = &mut cache.states[state as usize];
+                if c.is_ascii() {
+                    from.ascii[c as usize] = next;
+                } else {
+                    from.other.insert(c, next);
+                    cache.memory += mem::size_of::<(char, StateId)>() * 2;
+                }
+            }
+            next
+        };
+        match next {
+            MATCH => return Some(true),
+            DEAD => return Some(false),
+            _ => state = next,
+        }
+        at += len;
+    }
+    Some(matches!(
+        step(prog, cache, state, None, true, anchored),
+        Step::Match
+    ))
+}
+
+/// Whether the program can only match at the start of the haystack.
+fn is_anchored(prog: &Prog) -> bool {
+    matches!(
+        prog.insts[..],
+        [Inst::Save(0), Inst::Assert(Assertion::StartLine), ..]
+    )
+}
+
+enum Step {
+    Match,
+    Dead,
+    Next(Key),
+}
+
+/// Computes the transition out of `state` on `next`, or at the end of the
+/// haystack when `next` is `None`. `eol` tells whether `$` holds here.
+fn step(
+    prog: &Prog,
+    cache: &mut Cache,
+    state: StateId,
+    next: Option<char>,
+    eol: bool,
+    anchored: bool,
+) -> Step {
+    let Cache {
+        states, set, stack, ..
+    } = cache;
+    let key = &states[state as usize].key;
+    let word_after = next.is_some_and(|c| is_word_char(c, prog.unicode));
+    set.clear();
+    stack.extend(key.core.iter().rev());
+    let mut core = Vec::new();
+    while let Some(pc) = stack.pop() {
+        if !set.insert(pc) {
+            continue;
+        }
+        match &prog.insts[pc] {
+            Inst::Split(first, second) => {
+                stack.push(*second);
+                stack.push(*first);
+            }
+            Inst::Jmp(target) => stack.push(*target),
+            Inst::Save(_) | Inst::Mark(_) | Inst::Progress { .. } => stack.push(pc + 1),
+            Inst::Assert(assertion) => {
+                let holds = match assertion {
+                    Assertion::StartLine => key.start,
+                    Assertion::EndLine => eol,
+                    Assertion::WordBoundary => key.word != word_after,
+                    Assertion::NotWordBoundary => key.word == word_after,
+                    Assertion::NoWordBefore => !key.word,
+                    Assertion::NoWordAfter => !word_after,
+                };
+                if holds {
+                    stack.push(pc + 1);
+                }
+            }
+            Inst::Backref(_) => unreachable!("backreferences need the backtracker"),
+            Inst::Match => {
+                stack.clear();
+                return Step::Match;
+            }
+            inst => {
+                if next.is_some_and(|c| inst.matches_char(c)) {
+                    core.push(pc + 1);
+                }
+            }
+        }
+    }
+    let Some(c) = next else {
+        return Step::Dead;
+    };
+    if !anchored {
+        core.push(0);
+    }
+    if core.is_empty() {
+        return Step::Dead;
+    }
+    core.sort_unstable();
+    core.dedup();
+    Step::Next(Key {
+        core: core.into_boxed_slice(),
+        start: false,
+        word: is_word_char(c, prog.unicode),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{compile, Config};
+    use crate::parse::parse;
+    use crate::pikevm;
+
+    const PATTERNS: &[&str] = &[
+        "abc",
+        "a|b|cd",
+        "(a|b)*abb",
+        "[a-z]+[0-9]{2,3}",
+        "^a.c$",
+        "\\bfoo\\b",
+        "\\Bo+\\B",
+        "(x*)*y",
+        "é+[^a]",
+        "((a|ab)(c|bcd))(d*)",
+    ];
+
+    const HAYS: &[&str] = &[
+        "",
+        "abc",
+        "xxabbxyz",
+        "hello42 world999",
+        "a\u{e9}c",
+        "foo",
+        "food fool foo.",
+        "boon",
+        "xxxxxxxxxxy",
+        "\u{e9}\u{e9}\u{e9}a\u{e9}b",
+        "abcd",
+    ];
+
+    /// Whether the Pike VM finds a match, as the reference answer.
+    fn expected(prog: &Prog, hay: &[u8], start: usize) -> bool {
+        let mut cache = pikevm::Cache::new(prog);
+        pikevm::search(prog, &mut cache, hay, start, true, false, &mut [])
+    }
+
+    #[test]
+    fn agrees_with_the_pikevm_however_small_the_cache() {
+        for pattern in PATTERNS {
+            let config = Config {
+                unicode: true,
+                size_limit: 1 << 20,
+            };
+            let prog = compile(&parse(pattern).unwrap(), config).unwrap();
+            for limit in [0, 1000, 4000, 1 << 20] {
+                // One cache for every haystack, so states carry over.
+                let mut cache = Cache::new(&prog, limit);
+                for hay in HAYS {
+                    for start in [0, 1] {
+                        let hay = hay.as_bytes();
+                        if start > hay.len() {
+                            continue;
+                        }
+                        let want = expected(&prog, hay, start);
+                        if let Some(got) = is_match(&prog, &mut cache, hay, start) {
+                            assert_eq!(
+                                got, want,
+                                "{:?} on {:?} with limit {}",
+                                pattern, hay, limit
+                            );
+                        } else {
+                            assert!(limit < 1 << 20, "{:?} gave up with room to spare", pattern);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
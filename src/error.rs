@@ -0,0 +1,135 @@
+//! Errors reported for invalid patterns.
+
+use thiserror::Error;
+
+/// A syntax error in a pattern, or a pattern too large to compile.
+///
+/// `offset` is the byte offset of the offending character in the pattern;
+/// `column` is its position in its line of the pattern, counted in
+/// characters and starting at 1.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum PatternError {
+    #[error("unmatched ( at column {column}")]
+    UnmatchedParen { offset: usize, column: usize },
+    #[error("unmatched [ at column {column}")]
+    UnmatchedBracket { offset: usize, column: usize },
+    #[error("unmatched {{ at column {column}")]
+    UnmatchedBrace { offset: usize, column: usize },
+    #[error("trailing backslash at column {column}")]
+    TrailingBackslash { offset: usize, column: usize },
+    #[error("invalid range end at column {column}")]
+    InvalidRange { offset: usize, column: usize },
+    #[error("invalid repetition count at column {column}")]
+    InvalidRepetition { offset: usize, column: usize },
+    #[error("invalid back reference at column {column}")]
+    InvalidBackref { offset: usize, column: usize },
+    #[error("unknown character class '{name}' at column {column}")]
+    UnknownClass {
+        name: String,
+        offset: usize,
+        column: usize,
+    },
+    #[error("pattern nested too deeply at column {column}")]
+    NestingTooDeep { offset: usize, column: usize },
+    #[error("compiled pattern exceeds the size limit of {limit} bytes")]
+    TooBig { limit: usize },
+}
+
+impl PatternError {
+    /// The byte offset of the error in the pattern, if it has one.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            PatternError::UnmatchedParen { offset, .. }
+            | PatternError::UnmatchedBracket { offset, .. }
+            | PatternError::UnmatchedBrace { offset, .. }
+            | PatternError::TrailingBackslash { offset, .. }
+            | PatternError::InvalidRange { offset, .. }
+            | PatternError::InvalidRepetition { offset, .. }
+            | PatternError::InvalidBackref { offset, .. }
+            | PatternError::UnknownClass { offset, .. }
+            | PatternError::NestingTooDeep { offset, .. } => Some(offset),
+            PatternError::TooBig { .. } => None,
+        }
+    }
+
+    /// The 1-based character column of the error, if it has one.
+    pub fn column(&self) -> Option<usize> {
+        match *self {
+            PatternError::UnmatchedParen { column, .. }
+            | PatternError::UnmatchedBracket { column, .. }
+            | PatternError::UnmatchedBrace { column, .. }
+            | PatternError::TrailingBackslash { column, .. }
+            | PatternError::InvalidRange { column, .. }
+            | PatternError::InvalidRepetition { column, .. }
+            | PatternError::InvalidBackref { column, .. }
+            | PatternError::UnknownClass { column, .. }
+            | PatternError::NestingTooDeep { column, .. } => Some(column),
+            PatternError::TooBig { .. } => None,
+        }
+    }
+
+    /// Renders the error followed by the pattern, or the line of it the
+    /// error is in, and a caret under the offending character, e.g.
+    ///
+    /// ```text
+    /// grep: unmatched ( at column 4
+    ///   abc(d
+    ///      ^
+    /// ```
+    pub fn diagnostic(&self, pattern: &str) -> String {
+        let (Some(offset), Some(column)) = (self.offset(), self.column()) else {
+            return format!("grep: {}", self);
+        };
+        let start = pattern[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line = pattern[start..].split('\n').next().unwrap();
+        format!("grep: {}\n  {}\n  {}^", self, line, " ".repeat(column - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    fn diagnostic(pattern: &str) -> String {
+        parse(pattern).unwrap_err().diagnostic(pattern)
+    }
+
+    #[test]
+    fn caret_points_at_the_column() {
+        assert_eq!(
+            diagnostic("abc(d"),
+            "grep: unmatched ( at column 4\n  abc(d\n     ^"
+        );
+        assert_eq!(
+            diagnostic("a\\"),
+            "grep: trailing backslash at column 2\n  a\\\n   ^"
+        );
+        // Columns count characters, not bytes.
+        assert_eq!(
+            diagnostic("éé[b"),
+            "grep: unmatched [ at column 3\n  éé[b\n    ^"
+        );
+    }
+
+    #[test]
+    fn only_the_line_with_the_error_is_shown() {
+        assert_eq!(
+            diagnostic("ok\nab[cd\nmore"),
+            "grep: unmatched [ at column 3\n  ab[cd\n    ^"
+        );
+        assert_eq!(
+            diagnostic("ok\n\nx{2,1}"),
+            "grep: invalid repetition count at column 2\n  x{2,1}\n   ^"
+        );
+    }
+
+    #[test]
+    fn errors_without_a_position_have_no_caret() {
+        let err = PatternError::TooBig { limit: 10 };
+        assert_eq!(
+            err.diagnostic("a{1000}"),
+            "grep: compiled pattern exceeds the size limit of 10 bytes"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Simple Unicode case folding.
+//!
+//! Two characters match each other when case is ignored if they have the
+//! same simple case folding, which makes `k`, `K` and the Kelvin sign `K`
+//! equivalent, and likewise `s`, `S` and the long s `ſ`. The standard
+//! library's one-character case mappings link almost all such characters,
+//! so the characters equivalent to one are found by following them in both
+//! directions; [`REVERSE`] supplies the directions it has no mapping for.
+
+use std::ops::Deref;
+
+/// Pairs `(to, from)` where `from` maps to `to` in one case but nothing
+/// maps `to` back to `from`, sorted by `to`. Generated from the standard
+/// library's case mappings.
+const REVERSE: &[(char, char)] = &[
+    ('\u{53}', '\u{17F}'),
+    ('\u{6B}', '\u{212A}'),
+    ('\u{DF}', '\u{1E9E}'),
+    ('\u{E5}', '\u{212B}'),
+    ('\u{1C4}', '\u{1C5}'),
+    ('\u{1C6}', '\u{1C5}'),
+    ('\u{1C7}', '\u{1C8}'),
+    ('\u{1C9}', '\u{1C8}'),
+    ('\u{1CA}', '\u{1CB}'),
+    ('\u{1CC}', '\u{1CB}'),
+    ('\u{1F1}', '\u{1F2}'),
+    ('\u{1F3}', '\u{1F2}'),
+    ('\u{392}', '\u{3D0}'),
+    ('\u{395}', '\u{3F5}'),
+    ('\u{398}', '\u{3D1}'),
+    ('\u{399}', '\u{345}'),
+    ('\u{399}', '\u{1FBE}'),
+    ('\u{39A}', '\u{3F0}'),
+    ('\u{39C}', '\u{B5}'),
+    ('\u{3A0}', '\u{3D6}'),
+    ('\u{3A1}', '\u{3F1}'),
+    ('\u{3A3}', '\u{3C2}'),
+    ('\u{3A6}', '\u{3D5}'),
+    ('\u{3B8}', '\u{3F4}'),
+    ('\u{3C9}', '\u{2126}'),
+    ('\u{412}', '\u{1C80}'),
+    ('\u{414}', '\u{1C81}'),
+    ('\u{41E}', '\u{1C82}'),
+    ('\u{421}', '\u{1C83}'),
+    ('\u{422}', '\u{1C84}'),
+    ('\u{422}', '\u{1C85}'),
+    ('\u{42A}', '\u{1C86}'),
+    ('\u{462}', '\u{1C87}'),
+    ('\u{1E60}', '\u{1E9B}'),
+    ('\u{1F80}', '\u{1F88}'),
+    ('\u{1F81}', '\u{1F89}'),
+    ('\u{1F82}', '\u{1F8A}'),
+    ('\u{1F83}', '\u{1F8B}'),
+    ('\u{1F84}', '\u{1F8C}'),
+    ('\u{1F85}', '\u{1F8D}'),
+    ('\u{1F86}', '\u{1F8E}'),
+    ('\u{1F87}', '\u{1F8F}'),
+    ('\u{1F90}', '\u{1F98}'),
+    ('\u{1F91}', '\u{1F99}'),
+    ('\u{1F92}', '\u{1F9A}'),
+    ('\u{1F93}', '\u{1F9B}'),
+    ('\u{1F94}', '\u{1F9C}'),
+    ('\u{1F95}', '\u{1F9D}'),
+    ('\u{1F96}', '\u{1F9E}'),
+    ('\u{1F97}', '\u{1F9F}'),
+    ('\u{1FA0}', '\u{1FA8}'),
+    ('\u{1FA1}', '\u{1FA9}'),
+    ('\u{1FA2}', '\u{1FAA}'),
+    ('\u{1FA3}', '\u{1FAB}'),
+    ('\u{1FA4}', '\u{1FAC}'),
+    ('\u{1FA5}', '\u{1FAD}'),
+    ('\u{1FA6}', '\u{1FAE}'),
+    ('\u{1FA7}', '\u{1FAF}'),
+    ('\u{1FB3}', '\u{1FBC}'),
+    ('\u{1FC3}', '\u{1FCC}'),
+    ('\u{1FF3}', '\u{1FFC}'),
+    ('\u{A64A}', '\u{1C88}'),
+];
+
+/// The most characters that fold together, as `Θ`, `θ`, `ϑ` and `ϴ` do.
+const MAX_ORBIT: usize = 4;
+
+/// The characters that fold together with one, as returned by [`orbit`].
+/// Dereferences to a slice; it is kept inline, as the engines ask for one
+/// for every character they test against a class when case is ignored.
+#[derive(Clone, Copy, Debug)]
+pub struct Orbit {
+    chars: [char; MAX_ORBIT],
+    len: usize,
+}
+
+impl Orbit {
+    fn push(&mut self, c: char) {
+        if !self.contains(&c) {
+            self.chars[self.len] = c;
+            self.len += 1;
+        }
+    }
+}
+
+impl Deref for Orbit {
+    type Target = [char];
+
+    fn deref(&self) -> &[char] {
+        &self.chars[..self.len]
+    }
+}
+
+/// Returns the characters that match `c` when case is ignored, starting
+/// with `c` itself.
+pub fn orbit(c: char) -> Orbit {
+    let mut orbit = Orbit {
+        chars: [c; MAX_ORBIT],
+        len: 1,
+    };
+    // Of the ASCII letters, only k and s fold together with characters
+    // outside ASCII.
+    if c.is_ascii() && !matches!(c, 'k' | 'K' | 's' | 'S') {
+        if c.is_ascii_alphabetic() {
+            orbit.push((c as u8 ^ 0x20) as char);
+        }
+        return orbit;
+    }
+    let mut i = 0;
+    while i < orbit.len {
+        let c = orbit.chars[i];
+        add_mappings(&mut orbit, c);
+        i += 1;
+    }
+    orbit
+}
+
+/// Whether `a` and `b` match each other when case is ignored.
+pub fn equivalent(a: char, b: char) -> bool {
+    a == b || orbit(a).contains(&b)
+}
+
+/// Adds the characters `c` maps to in either case, and those that map to
+/// it, to `orbit`.
+fn add_mappings(orbit: &mut Orbit, c: char) {
+    // The dotless ı only folds together with I in Turkic languages, which
+    // simple case folding leaves out.
+    if c == 'ı' {
+        return;
+    }
+    let cases = [single_char(c.to_lowercase()), single_char(c.to_uppercase())];
+    for mapped in cases.into_iter().flatten() {
+        orbit.push(mapped);
+    }
+    let start = REVERSE.partition_point(|&(to, _)| to < c);
+    for &(to, from) in &REVERSE[start..] {
+        if to != c {
+            break;
+        }
+        orbit.push(from);
+    }
+}
+
+/// The only character of a case mapping, or `None` if it maps to several,
+/// as `ß` does to `SS`.
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(c: char) -> Vec<char> {
+        let mut chars = orbit(c).to_vec();
+        chars.sort_unstable();
+        chars
+    }
+
+    #[test]
+    fn orbits_are_the_same_from_every_member() {
+        let orbits: [&[char]; 4] = [
+            &['S', 's', 'ſ'],
+            &['K', 'k', '\u{212A}'],
+            &['ß', 'ẞ'],
+            &['Θ', 'θ', 'ϑ', 'ϴ'],
+        ];
+        for expected in orbits {
+            for &c in expected {
+                assert_eq!(sorted(c), expected, "orbit of {:?}", c);
+                assert_eq!(orbit(c)[0], c);
+            }
+        }
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(sorted('a'), ['A', 'a']);
+        assert_eq!(sorted('1'), ['1']);
+        assert!(equivalent('q', 'Q'));
+        assert!(!equivalent('q', 'p'));
+    }
+
+    #[test]
+    fn dotless_and_dotted_i_stay_apart() {
+        assert_eq!(sorted('ı'), ['ı']);
+        assert_eq!(sorted('i'), ['I', 'i']);
+        assert_eq!(sorted('İ'), ['İ']);
+    }
+
+    #[test]
+    fn multi_character_mappings_are_left_out() {
+        // ß uppercases to SS, which is not a single character.
+        assert!(!equivalent('ß', 's'));
+        assert_eq!(sorted('ŉ'), ['ŉ']);
+    }
+}
@@ -0,0 +1,416 @@
+//! `.gitignore` and `.ignore` rules for recursive search.
+//!
+//! Each directory's ignore files are parsed as it is entered and stacked on
+//! top of its parent's, so rules in deeper directories take precedence and
+//! are dropped again when the walk leaves them. Within a directory, `.ignore`
+//! takes precedence over `.gitignore`, and later lines over earlier ones.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The names of the ignore files read from each directory, lowest
+/// precedence first.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// The ignore rules in effect for one directory and its ancestors.
+#[derive(Debug, Default)]
+pub struct Ignore {
+    parent: Option<Arc<Ignore>>,
+    files: Vec<IgnoreFile>,
+}
+
+impl Ignore {
+    /// Returns the rules in effect inside `dir`, a child of the directory
+    /// these rules belong to.
+    pub fn add_dir(self: &Arc<Ignore>, dir: &Path) -> Arc<Ignore> {
+        self.add_files(dir, dir, Path::new(""))
+    }
+
+    /// Returns the rules in effect in `root`, where a walk starts, from the
+    /// ignore files in the directories above it. Like git, only those in
+    /// the same repository count, up to the directory holding `.git`;
+    /// outside a repository there are none. The files in `root` itself are
+    /// read when the walk enters it.
+    pub fn root(root: &Path) -> Arc<Ignore> {
+        let mut ignore = Arc::new(Ignore::default());
+        let Ok(absolute) = fs::canonicalize(root) else {
+            return ignore;
+        };
+        let Some(repo) = absolute.ancestors().find(|dir| dir.join(".git").exists()) else {
+            return ignore;
+        };
+        let parents: Vec<&Path> = absolute
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(repo))
+            .collect();
+        for parent in parents.into_iter().rev() {
+            let prefix = absolute.strip_prefix(parent).unwrap();
+            ignore = ignore.add_files(parent, root, prefix);
+        }
+        ignore
+    }
+
+    /// Stacks the ignore files in `from` on top of these rules. Their rules
+    /// are matched against paths below `dir`, which is `prefix` below
+    /// `from`.
+    fn add_files(self: &Arc<Ignore>, from: &Path, dir: &Path, prefix: &Path) -> Arc<Ignore> {
+        let files: Vec<IgnoreFile> = IGNORE_FILES
+            .iter()
+            .filter_map(|name| fs::read_to_string(from.join(name)).ok())
+            .map(|contents| IgnoreFile {
+                prefix: prefix.to_path_buf(),
+                ..IgnoreFile::parse(dir, &contents)
+            })
+            .filter(|file| !file.rules.is_empty())
+            .collect();
+        if files.is_empty() {
+            return Arc::clone(self);
+        }
+        Arc::new(Ignore {
+            parent: Some(Arc::clone(self)),
+            files,
+        })
+    }
+
+    /// Whether `path`, which lies below the directory these rules belong
+    /// to, should be skipped.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignore = Some(self);
+        while let Some(current) = ignore {
+            for file in current.files.iter().rev() {
+                if let Some(ignored) = file.matched(path, is_dir) {
+                    return ignored;
+                }
+            }
+            ignore = current.parent.as_deref();
+        }
+        false
+    }
+}
+
+/// The rules of a single ignore file.
+#[derive(Debug)]
+struct IgnoreFile {
+    /// The directory containing the file; rules match paths relative to it.
+    dir: PathBuf,
+    /// For a file above the directory a walk starts in, where `dir`, the
+    /// start, lies relative to the file. Empty otherwise.
+    prefix: PathBuf,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    glob: Vec<Token>,
+    /// `!pattern` re-includes what an earlier rule excluded.
+    negated: bool,
+    /// `pattern/` only matches directories.
+    dir_only: bool,
+}
+
+impl IgnoreFile {
+    fn parse(dir: &Path, contents: &str) -> IgnoreFile {
+        IgnoreFile {
+            dir: dir.to_path_buf(),
+            prefix: PathBuf::new(),
+            rules: contents.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    /// Returns whether the last rule matching `path` ignores it, or `None`
+    /// if no rule matches.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = self.prefix.join(path.strip_prefix(&self.dir).ok()?);
+        let relative: Vec<char> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+            .chars()
+            .collect();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && matches(&rule.glob, &relative))
+            .map(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        // Trailing spaces are dropped unless escaped with a backslash.
+        let mut line = line.trim_end_matches('\r');
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        // A slash anywhere but the end anchors the pattern to the ignore
+        // file's directory; otherwise it matches a name at any depth.
+        let mut glob = Vec::new();
+        let line = match line.strip_prefix('/') {
+            Some(rest) => rest,
+            None if !line.contains('/') => {
+                glob.push(Token::AnyDirs);
+                line
+            }
+            None => line,
+        };
+        glob.extend(tokenize(line));
+        Some(Rule {
+            glob,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Token {
+    Literal(char),
+    /// `?`
+    Any,
+    /// `*`, which does not cross a `/`.
+    Star,
+    /// A leading `**/`: zero or more leading directories.
+    AnyDirs,
+    /// `/**/`: a slash, or zero or more directories between slashes.
+    MidDirs,
+    /// A trailing `/**`: everything inside.
+    Inside,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+fn tokenize(glob: &str) -> Vec<Token> {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = &chars[i..];
+        let token = if i == 0 && rest.starts_with(&['*', '*', '/']) {
+            i += 3;
+            Token::AnyDirs
+        } else if rest.starts_with(&['/', '*', '*', '/']) {
+            i += 4;
+            Token::MidDirs
+        } else if rest == ['/', '*', '*'] {
+            i += 3;
+            Token::Inside
+        } else {
+            i += 1;
+            match rest[0] {
+                '\\' if rest.len() > 1 => {
+                    i += 1;
+                    Token::Literal(rest[1])
+                }
+                '?' => Token::Any,
+                '*' => {
+                    while chars.get(i) == Some(&'*') {
+                        i += 1;
+                    }
+                    Token::Star
+                }
+                '[' => match parse_class(&rest[1..]) {
+                    Some((token, len)) => {
+                        i += len;
+                        token
+                    }
+                    None => Token::Literal('['),
+                },
+                c => Token::Literal(c),
+            }
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Parses the inside of a `[...]` class, returning it and the number of
+/// characters consumed including the closing bracket.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        i += 1;
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i));
+        }
+        first = false;
+        let lo = if c == '\\' {
+            i += 1;
+            *chars.get(i - 1)?
+        } else {
+            c
+        };
+        match (chars.get(i), chars.get(i + 1)) {
+            (Some('-'), Some(&hi)) if hi != ']' => {
+                ranges.push((lo, hi));
+                i += 2;
+            }
+            _ => ranges.push((lo, lo)),
+        }
+    }
+}
+
+/// Matches a tokenized glob against a `/`-separated relative path.
+fn matches(glob: &[Token], path: &[char]) -> bool {
+    let Some((token, rest)) = glob.split_first() else {
+        return path.is_empty();
+    };
+    let (&c, tail) = match (token, path.split_first()) {
+        (Token::Star, _) => {
+            let end = path.iter().position(|&c| c == '/').unwrap_or(path.len());
+            return (0..=end).any(|i| matches(rest, &path[i..]));
+        }
+        (Token::AnyDirs, _) => {
+            return matches(rest, path)
+                || (0..path.len()).any(|i| path[i] == '/' && matches(rest, &path[i + 1..]));
+        }
+        (Token::MidDirs, _) => {
+            return path.first() == Some(&'/')
+                && (0..path.len()).any(|i| path[i] == '/' && matches(rest, &path[i + 1..]));
+        }
+        (Token::Inside, _) => return path.len() > 1 && path[0] == '/',
+        (_, None) => return false,
+        (_, Some(split)) => split,
+    };
+    let ok = match token {
+        Token::Literal(expected) => c == *expected,
+        Token::Any => c != '/',
+        Token::Class { negated, ranges } => {
+            c != '/' && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+        }
+        Token::Star | Token::AnyDirs | Token::MidDirs | Token::Inside => unreachable!(),
+    };
+    ok && matches(rest, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(contents: &str) -> IgnoreFile {
+        IgnoreFile::parse(Path::new("/repo"), contents)
+    }
+
+    fn ignored(file: &IgnoreFile, path: &str) -> Option<bool> {
+        let is_dir = path.ends_with('/');
+        let path = Path::new("/repo").join(path.trim_end_matches('/'));
+        file.matched(&path, is_dir)
+    }
+
+    #[test]
+    fn names_without_a_slash_match_at_any_depth() {
+        let file = file("*.log\nbuild/\n");
+        assert_eq!(ignored(&file, "a.log"), Some(true));
+        assert_eq!(ignored(&file, "src/deep/a.log"), Some(true));
+        assert_eq!(ignored(&file, "a.logs"), None);
+        assert_eq!(ignored(&file, "src/build/"), Some(true));
+        // A trailing slash only matches directories.
+        assert_eq!(ignored(&file, "src/build"), None);
+    }
+
+    #[test]
+    fn a_slash_anchors_to_the_directory_of_the_file() {
+        let file = file("/target\ndocs/*.html\n");
+        assert_eq!(ignored(&file, "target/"), Some(true));
+        assert_eq!(ignored(&file, "sub/target/"), None);
+        assert_eq!(ignored(&file, "docs/index.html"), Some(true));
+        assert_eq!(ignored(&file, "docs/api/index.html"), None);
+        assert_eq!(ignored(&file, "sub/docs/index.html"), None);
+    }
+
+    #[test]
+    fn later_negations_reinclude() {
+        let file = file("*.log\n!keep.log\n");
+        assert_eq!(ignored(&file, "a.log"), Some(true));
+        assert_eq!(ignored(&file, "keep.log"), Some(false));
+        assert_eq!(ignored(&file, "sub/keep.log"), Some(false));
+        let file = self::file("!keep.log\n*.log\n");
+        assert_eq!(ignored(&file, "keep.log"), Some(true));
+    }
+
+    #[test]
+    fn double_stars() {
+        let file = file("**/cache\nlogs/**\na/**/b\n");
+        assert_eq!(ignored(&file, "cache"), Some(true));
+        assert_eq!(ignored(&file, "x/y/cache"), Some(true));
+        assert_eq!(ignored(&file, "logs/today/x"), Some(true));
+        assert_eq!(ignored(&file, "logs/"), None);
+        assert_eq!(ignored(&file, "a/b"), Some(true));
+        assert_eq!(ignored(&file, "a/x/y/b"), Some(true));
+        assert_eq!(ignored(&file, "ab"), None);
+        assert_eq!(ignored(&file, "a/xb"), None);
+    }
+
+    #[test]
+    fn single_stars_and_classes_stay_within_a_name() {
+        let file = file("a*z\nfile[0-9]\n\\#lit\n");
+        assert_eq!(ignored(&file, "abcz"), Some(true));
+        assert_eq!(ignored(&file, "ab/cz"), None);
+        assert_eq!(ignored(&file, "file7"), Some(true));
+        assert_eq!(ignored(&file, "filex"), None);
+        assert_eq!(ignored(&file, "#lit"), Some(true));
+    }
+
+    #[test]
+    fn deeper_files_take_precedence() {
+        let root = Arc::new(Ignore {
+            parent: None,
+            files: vec![file("*.log\n")],
+        });
+        let sub = Ignore {
+            parent: Some(root),
+            files: vec![IgnoreFile::parse(Path::new("/repo/sub"), "!*.log\n")],
+        };
+        assert!(sub.is_ignored(Path::new("/repo/a.log"), false));
+        assert!(!sub.is_ignored(Path::new("/repo/sub/a.log"), false));
+        assert!(!sub.is_ignored(Path::new("/repo/a.txt"), false));
+    }
+
+    #[test]
+    fn files_above_the_start_apply_up_to_the_repository() {
+        let base = std::env::temp_dir().join(format!("grep-ignore-{}", std::process::id()));
+        let repo = base.join("repo");
+        let src = repo.join("src");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(&src).unwrap();
+        fs::write(base.join(".gitignore"), "*.txt\n").unwrap();
+        fs::write(repo.join(".gitignore"), "*.log\n/src/gen\n").unwrap();
+        fs::write(src.join(".ignore"), "!keep.log\n").unwrap();
+        let ignore = Ignore::root(&src).add_dir(&src);
+        assert!(ignore.is_ignored(&src.join("sub/z.log"), false));
+        assert!(ignore.is_ignored(&src.join("gen"), true));
+        assert!(!ignore.is_ignored(&src.join("sub/gen"), true));
+        assert!(!ignore.is_ignored(&src.join("keep.log"), false));
+        // The file above the repository is not part of it.
+        assert!(!ignore.is_ignored(&src.join("a.txt"), false));
+        // Outside a repository, nothing above the start is read.
+        fs::remove_dir(repo.join(".git")).unwrap();
+        assert!(!Ignore::root(&src).is_ignored(&src.join("z.log"), false));
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
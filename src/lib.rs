@@ -0,0 +1,30 @@
+//! A small grep: an extended regular expression engine and the pieces
+//! needed to drive it from the command line.
+
+mod args;
+pub mod ast;
+mod backtrack;
+mod class;
+mod color;
+mod compile;
+mod dfa;
+mod error;
+mod fold;
+mod ignore;
+pub mod parse;
+mod pikevm;
+pub mod pool;
+mod prefilter;
+mod regex;
+mod search;
+mod sparse;
+mod utf8;
+mod walk;
+
+pub use crate::args::{Args, ArgsError, CaseMode, ColorChoice};
+pub use crate::color::Colors;
+pub use crate::error::PatternError;
+pub use crate::parse::parse;
+pub use crate::regex::{Captures, Match, MatchKind, Matches, Regex, RegexBuilder};
+pub use crate::search::{SearchOptions, Searcher};
+pub use crate::walk::{WalkDir, WalkEntry, WalkError, WalkOptions};
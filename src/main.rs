@@ -1,34 +1,539 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::io;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Stdout, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{Condvar, Mutex};
+use std::thread;
 
-fn match_pattern(input_line: &str, pattern: &str) -> bool {
-    if pattern.chars().count() == 1 {
-        return input_line.contains(pattern);
+use grep_starter_rust::pool::{self, Worker};
+use grep_starter_rust::{
+    Args, CaseMode, ColorChoice, Colors, Regex, RegexBuilder, SearchOptions, Searcher, WalkDir,
+    WalkEntry, WalkError, WalkOptions,
+};
+
+// Usage: your_program.sh [OPTION]... PATTERNS [FILE]...
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("grep: {}", err);
+            eprintln!("{}", Args::USAGE);
+            eprintln!("Try 'grep --help' for more information.");
+            process::exit(2)
+        }
+    };
+    if args.help {
+        print!("{}", Args::HELP);
+        process::exit(0)
+    }
+    if args.version {
+        println!("grep {}", env!("CARGO_PKG_VERSION"));
+        process::exit(0)
+    }
+    // Several patterns are searched for as one, a line each.
+    let pattern = args.patterns.join("\n");
+    let regex = match RegexBuilder::new(&pattern)
+        .case_insensitive(args.case == CaseMode::Insensitive)
+        .case_smart(args.case == CaseMode::Smart)
+        .whole_word(args.word_regexp)
+        .whole_line(args.line_regexp)
+        .build()
+    {
+        Ok(regex) => regex,
+        Err(err) => {
+            eprintln!("{}", err.diagnostic(&pattern));
+            process::exit(2)
+        }
+    };
+
+    let mut paths = args.paths.clone();
+    // Without operands, -r searches the working directory and names files
+    // relative to it.
+    let implicit_cwd = paths.is_empty() && args.recursive;
+    if paths.is_empty() {
+        paths.push(if args.recursive { "." } else { "-" }.to_string());
+    }
+    let with_filename =
+        paths.len() > 1 || (args.recursive && paths.iter().any(|path| Path::new(path).is_dir()));
+    let threads = args
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()));
+
+    let search = SearchOptions {
+        invert: args.invert,
+        count: args.count,
+        line_number: args.line_number,
+        column: args.column,
+        byte_offset: args.byte_offset,
+        only_matching: args.only_matching,
+        before_context: args.before_context.or(args.context).unwrap_or(0),
+        after_context: args.after_context.or(args.context).unwrap_or(0),
+        group_separator: args.group_separator.clone(),
+        colors: use_color(args.color).then(|| match env::var("GREP_COLORS") {
+            Ok(spec) => Colors::parse(&spec),
+            Err(_) => Colors::default(),
+        }),
+    };
+    let mut output = Output {
+        out: BufWriter::new(io::stdout()),
+        separator: search.group_separator(),
+        printed: false,
+        pending: BTreeMap::new(),
+        finished: BTreeMap::new(),
+        direct: false,
+        matched: false,
+        failed: false,
+    };
+    // A lone file needs no threads, and its output no ordering.
+    let single =
+        paths.len() == 1 && (!args.recursive || paths[0] == "-" || !Path::new(&paths[0]).is_dir());
+    // Operands are always printed in the order they were given.
+    let jobs: Vec<Job> = paths
+        .into_iter()
+        .enumerate()
+        .map(|(i, path)| Job::Operand(vec![i], path))
+        .collect();
+    for job in &jobs {
+        output.start(job.key());
+    }
+    let grep = Grep {
+        options: WalkOptions {
+            follow_links: args.follow_links,
+            sort: args.sort,
+            hidden: args.hidden,
+            no_ignore: args.no_ignore,
+        },
+        search,
+        recursive: args.recursive,
+        with_filename,
+        implicit_cwd,
+        line_buffered: io::stdout().is_terminal(),
+        output: Mutex::new(output),
+        wake: Condvar::new(),
+    };
+    if single {
+        let Some(Job::Operand(key, path)) = jobs.into_iter().next() else {
+            unreachable!("a single operand is a single job");
+        };
+        grep.search_operand(&regex, key, &path);
     } else {
-        panic!("Unhandled pattern: {}", pattern)
+        // The regex keeps scratch space of its own, so every thread gets a
+        // copy.
+        let regexes = (0..threads).map(|_| regex.clone()).collect();
+        pool::run(regexes, jobs, |regex, job, worker| {
+            grep.run(regex, job, worker)
+        });
     }
+
+    let mut output = grep.output.into_inner().unwrap();
+    output.flush();
+    process::exit(match (output.failed, output.matched) {
+        (true, _) => 2,
+        (false, true) => 0,
+        (false, false) => 1,
+    })
 }
 
-// Usage: echo <input_text> | your_program.sh -E <pattern>
-fn main() {
-    // You can use print statements as follows for debugging, they'll be visible when running tests.
-    println!("Logs from your program will appear here!");
+/// Where a job's output goes relative to the others'. Jobs print in the
+/// order of their keys; jobs with equal keys print in whatever order they
+/// finish.
+type Key = Vec<usize>;
 
-    if env::args().nth(1).unwrap() != "-E" {
-        println!("Expected first argument to be '-E'");
-        process::exit(1);
+/// A unit of work for the thread pool.
+enum Job {
+    /// A path named on the command line.
+    Operand(Key, String),
+    /// A directory found while recursing.
+    Dir(Key, WalkDir),
+    /// A file found while recursing.
+    File(Key, PathBuf),
+}
+
+impl Job {
+    fn key(&self) -> Key {
+        match self {
+            Job::Operand(key, _) | Job::Dir(key, _) | Job::File(key, _) => key.clone(),
+        }
     }
+}
 
-    let pattern = env::args().nth(2).unwrap();
-    let mut input_line = String::new();
+/// The settings of one grep run, shared by all of its threads.
+struct Grep {
+    options: WalkOptions,
+    search: SearchOptions,
+    recursive: bool,
+    with_filename: bool,
+    implicit_cwd: bool,
+    /// Whether standard output is a terminal, where each line is printed
+    /// as soon as it is found.
+    line_buffered: bool,
+    output: Mutex<Output>,
+    /// Signalled when a job finishes, for jobs waiting to print.
+    wake: Condvar,
+}
 
-    io::stdin().read_line(&mut input_line).unwrap();
+impl Grep {
+    fn run(&self, regex: &Regex, job: Job, worker: &Worker<Job>) {
+        match job {
+            Job::Operand(key, path) if self.recursive && path != "-" => {
+                match WalkEntry::root(Path::new(&path), &self.options) {
+                    Ok(WalkEntry::File(path)) => self.search_file(regex, key, &path),
+                    Ok(WalkEntry::Dir(dir)) => self.read_dir(key, dir, worker),
+                    Err(err) => self.finish(key, self.walk_error(err)),
+                }
+            }
+            Job::Operand(key, path) => self.search_operand(regex, key, &path),
+            Job::Dir(key, dir) => self.read_dir(key, dir, worker),
+            Job::File(key, path) => self.search_file(regex, key, &path),
+        }
+    }
 
-    // Uncomment this block to pass the first stage
-    if match_pattern(&input_line, &pattern) {
-        process::exit(0)
-    } else {
-        process::exit(1)
+    /// Searches an operand that is not a directory to recurse into, with
+    /// `-` standing for standard input.
+    fn search_operand(&self, regex: &Regex, key: Key, path: &str) {
+        if path == "-" {
+            let report = self.search(regex, &key, "(standard input)", io::stdin().lock());
+            self.finish(key, report);
+        } else {
+            self.search_file(regex, key, Path::new(path));
+        }
+    }
+
+    /// Queues a job for each entry of `dir`.
+    fn read_dir(&self, key: Key, dir: WalkDir, worker: &Worker<Job>) {
+        let (entries, report) = match dir.read(&self.options) {
+            Ok(entries) => (entries, Report::default()),
+            Err(err) => (Vec::new(), self.walk_error(err)),
+        };
+        let mut jobs = Vec::new();
+        let mut errors = Vec::new();
+        for (i, entry) in entries.into_iter().enumerate() {
+            // Sorted output puts each entry after the ones before it in the
+            // directory; otherwise, only the order of operands is kept.
+            let mut key = key.clone();
+            if self.options.sort {
+                key.push(i);
+            }
+            match entry {
+                Ok(WalkEntry::File(path)) => jobs.push(Job::File(key, path)),
+                Ok(WalkEntry::Dir(dir)) => jobs.push(Job::Dir(key, dir)),
+                Err(err) => errors.push((key, self.walk_error(err))),
+            }
+        }
+        // The new jobs must be pending before this one finishes, or output
+        // after them could be printed first.
+        let mut output = self.output.lock().unwrap();
+        for job in &jobs {
+            output.start(job.key());
+        }
+        for (key, report) in errors {
+            output.start(key.clone());
+            output.finish(key, report);
+        }
+        output.finish(key, report);
+        drop(output);
+        self.wake.notify_all();
+        // Workers take their newest job first, so queue the first entry last.
+        for job in jobs.into_iter().rev() {
+            worker.push(job);
+        }
+    }
+
+    fn search_file(&self, regex: &Regex, key: Key, path: &Path) {
+        let label = self.label(path);
+        let report = match File::open(path) {
+            Ok(file) => self.search(regex, &key, &label, file),
+            Err(err) => Report::error(&label, &err),
+        };
+        self.finish(key, report);
+    }
+
+    fn search(&self, regex: &Regex, key: &Key, label: &str, input: impl Read) -> Report {
+        let reader = BufReader::with_capacity(64 * 1024, input);
+        let filename = self.with_filename.then_some(label);
+        let mut sink = Sink {
+            grep: self,
+            key,
+            buffer: Vec::new(),
+            direct: false,
+        };
+        let result = Searcher::new(regex, &self.search).search(reader, &mut sink, filename);
+        let mut report = Report {
+            out: sink.buffer,
+            direct: sink.direct,
+            ..Report::default()
+        };
+        match result {
+            Ok(found) => report.matched = found,
+            Err(err) => {
+                report.messages.push(error_line(label, &err));
+                report.failed = true;
+            }
+        }
+        report
+    }
+
+    fn walk_error(&self, err: WalkError) -> Report {
+        match err {
+            WalkError::Io { path, err } => Report::error(&self.label(&path), &err),
+            WalkError::Loop { path } => Report {
+                messages: vec![format!(
+                    "grep: warning: {}: recursive directory loop",
+                    self.label(&path)
+                )],
+                ..Report::default()
+            },
+        }
+    }
+
+    /// The name `path` is printed under.
+    fn label(&self, path: &Path) -> String {
+        let path = match self.implicit_cwd {
+            true => path.strip_prefix(".").unwrap_or(path),
+            false => path,
+        };
+        path.display().to_string()
+    }
+
+    fn finish(&self, key: Key, report: Report) {
+        let mut output = self.output.lock().unwrap();
+        output.finish(key, report);
+        if self.line_buffered {
+            output.flush();
+        }
+        drop(output);
+        self.wake.notify_all();
+    }
+}
+
+/// The most output a job buffers while an earlier job is still running.
+/// Past it, the job waits for its turn to print.
+const BUFFER_LIMIT: usize = 1 << 20;
+
+/// How much output a job collects before handing it on.
+const CHUNK: usize = 64 * 1024;
+
+/// Where a search writes its output: straight to standard output once the
+/// job is the earliest one still pending, and into a buffer before that.
+struct Sink<'g> {
+    grep: &'g Grep,
+    key: &'g Key,
+    buffer: Vec<u8>,
+    /// Whether the job holds standard output, which it then keeps until it
+    /// finishes.
+    direct: bool,
+}
+
+impl Sink<'_> {
+    /// Prints the buffer if it is this job's turn. Otherwise it is kept,
+    /// unless it has grown past [`BUFFER_LIMIT`], in which case this waits
+    /// for the turn to come.
+    ///
+    /// Waiting cannot deadlock the pool: a worker runs the earliest job in
+    /// its own queue, and only queues jobs that come after the one it is
+    /// running, so the earliest pending job is always running and never
+    /// waits.
+    fn drain(&mut self) {
+        let grep = self.grep;
+        let mut output = grep.output.lock().unwrap();
+        let continued = self.direct;
+        if !self.direct {
+            while !output.claim(self.key) {
+                if self.buffer.len() < BUFFER_LIMIT {
+                    return;
+                }
+                output = grep.wake.wait(output).unwrap();
+            }
+            self.direct = true;
+        }
+        output.write(&self.buffer, continued);
+        if grep.line_buffered {
+            output.flush();
+        }
+        self.buffer.clear();
+    }
+}
+
+impl Write for Sink<'_> {
+    /// Never fails; errors writing standard output are handled by
+    /// [`Output`].
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK || (self.grep.line_buffered && buf.ends_with(b"\n")) {
+            self.drain();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What one job prints, or what is left of it if it printed directly, so
+/// that it can be printed in one piece and in order.
+#[derive(Default)]
+struct Report {
+    out: Vec<u8>,
+    /// Whether the job printed the start of its output directly, so `out`
+    /// continues it.
+    direct: bool,
+    /// Lines for standard error, printed after `out`.
+    messages: Vec<String>,
+    matched: bool,
+    failed: bool,
+}
+
+impl Report {
+    /// Reports an error about one input; the search carries on with the
+    /// next.
+    fn error(label: &str, err: &io::Error) -> Report {
+        Report {
+            messages: vec![error_line(label, err)],
+            failed: true,
+            ..Report::default()
+        }
+    }
+}
+
+/// Prints the reports of finished jobs once every job before them has
+/// finished too.
+struct Output {
+    out: BufWriter<Stdout>,
+    /// The group separator, which also goes between the output of
+    /// different inputs when context is printed.
+    separator: Option<Vec<u8>>,
+    /// Whether any lines have been printed yet.
+    printed: bool,
+    /// The keys of the jobs queued or running, with how many there are of
+    /// each.
+    pending: BTreeMap<Key, usize>,
+    /// Reports waiting for an earlier job to finish.
+    finished: BTreeMap<Key, Vec<Report>>,
+    /// Whether a running job is printing directly; nothing else may be
+    /// printed until it finishes.
+    direct: bool,
+    matched: bool,
+    failed: bool,
+}
+
+impl Output {
+    fn start(&mut self, key: Key) {
+        *self.pending.entry(key).or_default() += 1;
+    }
+
+    fn finish(&mut self, key: Key, report: Report) {
+        match self.pending.get_mut(&key) {
+            Some(1) => {
+                self.pending.remove(&key);
+            }
+            Some(count) => *count -= 1,
+            None => {}
+        }
+        if report.direct {
+            self.print(report);
+            self.direct = false;
+        } else {
+            self.finished.entry(key).or_default().push(report);
+        }
+        if self.direct {
+            return;
+        }
+        // Jobs only ever queue jobs with larger or equal keys, so nothing
+        // can come before a report whose key is at most the smallest
+        // pending one.
+        while let Some(entry) = self.finished.first_entry() {
+            if let Some((first, _)) = self.pending.first_key_value() {
+                if first < entry.key() {
+                    break;
+                }
+            }
+            for report in entry.remove() {
+                self.print(report);
+            }
+        }
+    }
+
+    /// Lets the job with `key` print directly if every job before it has
+    /// printed, and no other job is printing. Returns whether it may.
+    fn claim(&mut self, key: &Key) -> bool {
+        let first = self.pending.first_key_value().map(|(first, _)| first);
+        if self.direct || first != Some(key) {
+            return false;
+        }
+        self.direct = true;
+        true
+    }
+
+    fn print(&mut self, report: Report) {
+        self.matched |= report.matched;
+        self.failed |= report.failed;
+        self.write(&report.out, report.direct);
+        if !report.messages.is_empty() {
+            // Keep the output ahead of the messages about it.
+            self.flush();
+            for message in report.messages {
+                eprintln!("{}", message);
+            }
+        }
+    }
+
+    /// Writes output of one job, preceded by a separator if it is the
+    /// start of it and another input's output came before.
+    fn write(&mut self, out: &[u8], continued: bool) {
+        if out.is_empty() {
+            return;
+        }
+        if let (Some(separator), false, true) = (&self.separator, continued, self.printed) {
+            if let Err(err) = self.out.write_all(separator) {
+                self.write_error(&err);
+            }
+        }
+        self.printed = true;
+        if let Err(err) = self.out.write_all(out) {
+            self.write_error(&err);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.out.flush() {
+            self.write_error(&err);
+        }
+    }
+
+    fn write_error(&mut self, err: &io::Error) {
+        // A closed pipe, as in `grep ... | head`, just ends the search.
+        if err.kind() == io::ErrorKind::BrokenPipe {
+            process::exit(0);
+        }
+        eprintln!("grep: {}", error_message(err));
+        self.failed = true;
+    }
+}
+
+/// Whether `--color=WHEN` asks for colors here.
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Never => false,
+        ColorChoice::Always => true,
+        ColorChoice::Auto => {
+            io::stdout().is_terminal() && env::var("TERM").is_ok_and(|term| term != "dumb")
+        }
+    }
+}
+
+fn error_line(label: &str, err: &io::Error) -> String {
+    format!("grep: {}: {}", label, error_message(err))
+}
+
+/// Formats an I/O error like the C library's `strerror`, without the
+/// "(os error N)" suffix Rust appends.
+fn error_message(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error ") {
+        Some(end) => message[..end].to_string(),
+        None => message,
     }
 }
@@ -0,0 +1,701 @@
+//! Recursive-descent parser turning pattern text into an [`Ast`].
+
+use crate::ast::{
+    Assertion, Ast, Backref, Class, ClassItem, Group, PerlClass, PosixClass, Repetition,
+};
+use crate::error::PatternError;
+use crate::fold;
+
+type Result<T> = std::result::Result<T, PatternError>;
+
+/// The most groups and repetitions one part of a pattern may be nested in.
+/// The passes over the syntax tree recurse, so without a limit a pattern
+/// such as 20000 nested parentheses would overflow the stack.
+const NEST_LIMIT: usize = 250;
+
+/// Parses an extended regular expression.
+///
+/// A pattern of several lines is a list of patterns, one per line, and
+/// matches wherever any of them does, as with several `-e` options. Each
+/// line numbers its own back-references, so `\1` on the second line refers
+/// to that line's first group.
+pub fn parse(pattern: &str) -> Result<Ast> {
+    parse_with(pattern, Flags::default())
+}
+
+/// The flags a pattern starts out with.
+///
+/// Inside a pattern, `(?i)` turns on ignoring case up to the end of the
+/// enclosing group and `(?-i)` turns it off again; `(?i:...)` and
+/// `(?-i:...)` do the same for a non-capturing group. Each line of a
+/// pattern starts over with these flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// Ignore case, using simple Unicode case folding, so `k` also matches
+    /// `K` and the Kelvin sign `K`.
+    pub case_insensitive: bool,
+}
+
+/// Like [`parse`], but starting with `flags` instead of the defaults.
+pub fn parse_with(pattern: &str, flags: Flags) -> Result<Ast> {
+    let mut branches = Vec::new();
+    let mut base = 0;
+    let mut captures = 0;
+    for line in pattern.split('\n') {
+        let mut parser = Parser {
+            pattern: line,
+            base,
+            pos: 0,
+            depth: 0,
+            captures,
+            first_capture: captures,
+            flags,
+        };
+        branches.push(parser.parse_alternation()?);
+        base += line.len() + 1;
+        captures = parser.captures;
+    }
+    Ok(if branches.len() == 1 {
+        branches.pop().unwrap()
+    } else {
+        Ast::Alternation(branches)
+    })
+}
+
+struct Parser<'p> {
+    /// The line being parsed.
+    pattern: &'p str,
+    /// Byte offset of the line in the whole pattern.
+    base: usize,
+    /// Byte offset of the next unparsed character.
+    pos: usize,
+    /// Number of currently open groups.
+    depth: usize,
+    /// Number of capturing groups opened so far, including those of
+    /// earlier lines.
+    captures: usize,
+    /// Number of capturing groups in earlier lines.
+    first_capture: usize,
+    /// The flags in effect at `pos`.
+    flags: Flags,
+}
+
+impl<'p> Parser<'p> {
+    fn peek(&self) -> Option<char> {
+        self.pattern[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the byte offset in the whole pattern and the 1-based
+    /// character column in the line of the character at `offset`, for
+    /// building a [`PatternError`].
+    fn position(&self, offset: usize) -> (usize, usize) {
+        (
+            self.base + offset,
+            self.pattern[..offset].chars().count() + 1,
+        )
+    }
+
+    fn parse_alternation(&mut self) -> Result<Ast> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Ast::Alternation(branches)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Ast> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some('|') => break,
+                Some(')') if self.depth > 0 => break,
+                _ => {}
+            }
+            let start = self.pos;
+            let atom = match self.parse_flags() {
+                // The flags hold for the rest of the group.
+                Some(flags) if self.eat(')') => {
+                    self.flags = flags;
+                    continue;
+                }
+                Some(flags) => {
+                    self.bump();
+                    self.parse_group(start, flags, None)?
+                }
+                None => self.parse_atom()?,
+            };
+            items.push(self.parse_repetition(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Ast::Empty,
+            1 => items.pop().unwrap(),
+            _ => Ast::Concat(items),
+        })
+    }
+
+    fn parse_repetition(&mut self, mut ast: Ast) -> Result<Ast> {
+        // Like GNU grep, a quantifier after an anchor is a literal.
+        if let Ast::Assertion(_) = ast {
+            return Ok(ast);
+        }
+        // Worked out on the first quantifier, as most atoms have none.
+        let mut nesting = None;
+        loop {
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('{') => match self.parse_interval()? {
+                    Some(bounds) => bounds,
+                    None => return Ok(ast),
+                },
+                Some(c @ ('*' | '+' | '?')) => {
+                    self.bump();
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => return Ok(ast),
+            };
+            let nesting = nesting.get_or_insert_with(|| self.depth + depth(&ast));
+            *nesting += 1;
+            if *nesting > NEST_LIMIT {
+                let (offset, column) = self.position(start);
+                return Err(PatternError::NestingTooDeep { offset, column });
+            }
+            ast = Ast::Repetition(Repetition {
+                min,
+                max,
+                ast: Box::new(ast),
+            });
+        }
+    }
+
+    /// Parses a bounded repetition `{n}`, `{n,}`, `{,m}` or `{n,m}`. As in
+    /// GNU grep, a `{` that does not start a repetition count is left in
+    /// place to be parsed as a literal.
+    fn parse_interval(&mut self) -> Result<Option<(u32, Option<u32>)>> {
+        let start = self.pos;
+        let rest = &self.pattern[start + 1..];
+        if !rest.starts_with(|c: char| c.is_ascii_digit() || c == ',') {
+            return Ok(None);
+        }
+        let Some(len) = rest.find('}') else {
+            let (offset, column) = self.position(start);
+            return Err(PatternError::UnmatchedBrace { offset, column });
+        };
+        let invalid = || {
+            let (offset, column) = self.position(start);
+            PatternError::InvalidRepetition { offset, column }
+        };
+        let count = |digits: &str| match digits {
+            "" => Ok(None),
+            _ if digits.bytes().all(|b| b.is_ascii_digit()) => {
+                digits.parse().map(Some).map_err(|_| invalid())
+            }
+            _ => Err(invalid()),
+        };
+        let body = &rest[..len];
+        let (min, max) = match body.split_once(',') {
+            Some((min, max)) => (count(min)?.unwrap_or(0), count(max)?),
+            None => {
+                let n = count(body)?;
+                (n.unwrap_or(0), n)
+            }
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(invalid());
+        }
+        self.pos = start + 1 + len + 1;
+        Ok(Some((min, max)))
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast> {
+        let start = self.pos;
+        // Callers only parse an atom when input remains.
+        let c = self.bump().unwrap();
+        Ok(match c {
+            '(' => {
+                self.captures += 1;
+                self.parse_group(start, self.flags, Some(self.captures))?
+            }
+            '[' => Ast::Class(self.parse_class(start)?),
+            '.' => Ast::Dot,
+            '^' => Ast::Assertion(Assertion::StartLine),
+            '$' => Ast::Assertion(Assertion::EndLine),
+            '\\' => match self.bump() {
+                Some('b') => Ast::Assertion(Assertion::WordBoundary),
+                Some('B') => Ast::Assertion(Assertion::NotWordBoundary),
+                Some(c @ '1'..='9') => {
+                    // Only groups opened before the reference can be named.
+                    let index = c as usize - '0' as usize;
+                    if index > self.captures - self.first_capture {
+                        let (offset, column) = self.position(start);
+                        return Err(PatternError::InvalidBackref { offset, column });
+                    }
+                    Ast::Backref(Backref {
+                        index: self.first_capture + index,
+                        fold: self.flags.case_insensitive,
+                    })
+                }
+                Some(c) => match perl_class(c) {
+                    Some(item) => Ast::Class(Class {
+                        negated: false,
+                        fold: self.flags.case_insensitive,
+                        items: vec![item],
+                    }),
+                    None => self.literal(c),
+                },
+                None => {
+                    let (offset, column) = self.position(start);
+                    return Err(PatternError::TrailingBackslash { offset, column });
+                }
+            },
+            // Quantifiers with nothing to repeat, and `)` outside of any
+            // group, stand for themselves.
+            c => self.literal(c),
+        })
+    }
+
+    /// Parses the rest of a group opened at `start`, up to and including
+    /// its `)`, with `flags` in effect inside it. Non-capturing groups have
+    /// no `index`.
+    fn parse_group(&mut self, start: usize, flags: Flags, index: Option<usize>) -> Result<Ast> {
+        let outer = self.flags;
+        self.flags = flags;
+        if self.depth == NEST_LIMIT {
+            let (offset, column) = self.position(start);
+            return Err(PatternError::NestingTooDeep { offset, column });
+        }
+        self.depth += 1;
+        let ast = self.parse_alternation()?;
+        if !self.eat(')') {
+            let (offset, column) = self.position(start);
+            return Err(PatternError::UnmatchedParen { offset, column });
+        }
+        self.depth -= 1;
+        self.flags = outer;
+        Ok(match index {
+            Some(index) => Ast::Group(Group {
+                index,
+                ast: Box::new(ast),
+            }),
+            None => ast,
+        })
+    }
+
+    /// Parses the start of a flag group, `(?i` or `(?-i`, and returns the
+    /// flags it sets, leaving the `:` or `)` after it. Anything else is
+    /// left alone and parsed as before flags existed: a `(` followed by a
+    /// literal `?`.
+    fn parse_flags(&mut self) -> Option<Flags> {
+        let rest = self.pattern[self.pos..].strip_prefix("(?")?;
+        let (case_insensitive, rest) = match rest.strip_prefix('-') {
+            Some(rest) => (false, rest),
+            None => (true, rest),
+        };
+        let len = rest.len() - rest.trim_start_matches('i').len();
+        if len == 0 || !rest[len..].starts_with([':', ')']) {
+            return None;
+        }
+        self.pos = self.pattern.len() - rest.len() + len;
+        Some(Flags { case_insensitive })
+    }
+
+    /// The atom matching `c`, or any character it folds together with if
+    /// case is ignored.
+    fn literal(&self, c: char) -> Ast {
+        let orbit = fold::orbit(c);
+        if !self.flags.case_insensitive || orbit.len() == 1 {
+            return Ast::Literal(c);
+        }
+        Ast::Class(Class {
+            negated: false,
+            fold: false,
+            items: orbit.iter().map(|&c| ClassItem::Range(c, c)).collect(),
+        })
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<Class> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        // A `]` right after the opening bracket is a member, not the end.
+        let mut first = true;
+        loop {
+            if !first && self.eat(']') {
+                break;
+            }
+            first = false;
+            let lo = match self.parse_class_atom(start)? {
+                ClassAtom::Char(c) => c,
+                ClassAtom::Item(item) => {
+                    items.push(item);
+                    continue;
+                }
+            };
+            // A `-` right before the closing bracket is a literal member.
+            let rest = &self.pattern[self.pos..];
+            if !rest.starts_with('-') || rest.starts_with("-]") {
+                items.push(ClassItem::Range(lo, lo));
+                continue;
+            }
+            let range_start = self.pos - lo.len_utf8();
+            self.bump();
+            match self.parse_class_atom(start)? {
+                ClassAtom::Char(hi) if lo <= hi => items.push(ClassItem::Range(lo, hi)),
+                _ => {
+                    let (offset, column) = self.position(range_start);
+                    return Err(PatternError::InvalidRange { offset, column });
+                }
+            }
+        }
+        Ok(Class {
+            negated,
+            fold: self.flags.case_insensitive,
+            items,
+        })
+    }
+
+    /// Parses one member of the bracket expression opened at `start`.
+    fn parse_class_atom(&mut self, start: usize) -> Result<ClassAtom> {
+        let atom_start = self.pos;
+        let atom = match self.bump() {
+            Some('[') if self.pattern[self.pos..].starts_with(':') => {
+                match self.pattern[self.pos + 1..].find(":]") {
+                    Some(len) => {
+                        let name = &self.pattern[self.pos + 1..self.pos + 1 + len];
+                        let Some(class) = posix_class(name) else {
+                            let (offset, column) = self.position(atom_start);
+                            return Err(PatternError::UnknownClass {
+                                name: name.to_string(),
+                                offset,
+                                column,
+                            });
+                        };
+                        self.pos += len + 3;
+                        Some(ClassAtom::Item(ClassItem::Posix(class)))
+                    }
+                    None => Some(ClassAtom::Char('[')),
+                }
+            }
+            Some('\\') => self.bump().map(|c| match perl_class(c) {
+                Some(item) => ClassAtom::Item(item),
+                None => ClassAtom::Char(c),
+            }),
+            c => c.map(ClassAtom::Char),
+        };
+        atom.ok_or_else(|| {
+            let (offset, column) = self.position(start);
+            PatternError::UnmatchedBracket { offset, column }
+        })
+    }
+}
+
+/// The number of groups and repetitions nested in `ast` at its deepest.
+fn depth(ast: &Ast) -> usize {
+    match ast {
+        Ast::Group(group) => 1 + depth(&group.ast),
+        Ast::Repetition(rep) => 1 + depth(&rep.ast),
+        Ast::Concat(items) | Ast::Alternation(items) => items.iter().map(depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// A single member of a bracket expression, before ranges are formed.
+enum ClassAtom {
+    Char(char),
+    Item(ClassItem),
+}
+
+fn posix_class(name: &str) -> Option<PosixClass> {
+    Some(match name {
+        "alnum" => PosixClass::Alnum,
+        "alpha" => PosixClass::Alpha,
+        "blank" => PosixClass::Blank,
+        "cntrl" => PosixClass::Cntrl,
+        "digit" => PosixClass::Digit,
+        "graph" => PosixClass::Graph,
+        "lower" => PosixClass::Lower,
+        "print" => PosixClass::Print,
+        "punct" => PosixClass::Punct,
+        "space" => PosixClass::Space,
+        "upper" => PosixClass::Upper,
+        "xdigit" => PosixClass::XDigit,
+        _ => return None,
+    })
+}
+
+/// Maps the letter of a shorthand escape like `\d` or `\W` to its class.
+fn perl_class(c: char) -> Option<ClassItem> {
+    let class = match c.to_ascii_lowercase() {
+        'd' => PerlClass::Digit,
+        'w' => PerlClass::Word,
+        's' => PerlClass::Space,
+        _ => return None,
+    };
+    Some(ClassItem::Perl {
+        class,
+        negated: c.is_ascii_uppercase(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(index: usize, ast: Ast) -> Ast {
+        Ast::Group(Group {
+            index,
+            ast: Box::new(ast),
+        })
+    }
+
+    fn repetition(min: u32, max: Option<u32>, ast: Ast) -> Ast {
+        Ast::Repetition(Repetition {
+            min,
+            max,
+            ast: Box::new(ast),
+        })
+    }
+
+    #[test]
+    fn alternation_binds_looser_than_concatenation() {
+        let ast = parse("ab|c").unwrap();
+        assert_eq!(
+            ast,
+            Ast::Alternation(vec![
+                Ast::Concat(vec![Ast::Literal('a'), Ast::Literal('b')]),
+                Ast::Literal('c'),
+            ])
+        );
+    }
+
+    #[test]
+    fn quantifiers_stack_on_their_atom() {
+        assert_eq!(
+            parse("(a)+?").unwrap(),
+            repetition(0, Some(1), repetition(1, None, group(1, Ast::Literal('a'))))
+        );
+        assert_eq!(
+            parse("a{2,}").unwrap(),
+            repetition(2, None, Ast::Literal('a'))
+        );
+        assert_eq!(
+            parse("a{,3}").unwrap(),
+            repetition(0, Some(3), Ast::Literal('a'))
+        );
+    }
+
+    #[test]
+    fn stray_metacharacters_are_literals() {
+        assert_eq!(parse("*").unwrap(), Ast::Literal('*'));
+        assert_eq!(parse(")").unwrap(), Ast::Literal(')'));
+        assert_eq!(
+            parse("a{x").unwrap(),
+            Ast::Concat(vec![
+                Ast::Literal('a'),
+                Ast::Literal('{'),
+                Ast::Literal('x')
+            ])
+        );
+        assert_eq!(
+            parse("^*").unwrap(),
+            Ast::Concat(vec![
+                Ast::Assertion(Assertion::StartLine),
+                Ast::Literal('*')
+            ])
+        );
+    }
+
+    #[test]
+    fn classes() {
+        let ast = parse("[]a-c[:digit:]\\w-]").unwrap();
+        assert_eq!(
+            ast,
+            Ast::Class(Class {
+                negated: false,
+                fold: false,
+                items: vec![
+                    ClassItem::Range(']', ']'),
+                    ClassItem::Range('a', 'c'),
+                    ClassItem::Posix(PosixClass::Digit),
+                    ClassItem::Perl {
+                        class: PerlClass::Word,
+                        negated: false
+                    },
+                    ClassItem::Range('-', '-'),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn lines_number_their_own_backrefs() {
+        let ast = parse("(a)\\1\n(b)\\1").unwrap();
+        let Ast::Alternation(lines) = ast else {
+            panic!("expected one branch per line");
+        };
+        let backref = |index| Ast::Backref(Backref { index, fold: false });
+        assert_eq!(
+            lines[0],
+            Ast::Concat(vec![group(1, Ast::Literal('a')), backref(1)])
+        );
+        assert_eq!(
+            lines[1],
+            Ast::Concat(vec![group(2, Ast::Literal('b')), backref(2)])
+        );
+    }
+
+    #[test]
+    fn flag_groups_fold_literals_until_the_group_ends() {
+        let folded = Ast::Class(Class {
+            negated: false,
+            fold: false,
+            items: vec![ClassItem::Range('a', 'a'), ClassItem::Range('A', 'A')],
+        });
+        assert_eq!(
+            parse("((?i)a)a").unwrap(),
+            Ast::Concat(vec![group(1, folded.clone()), Ast::Literal('a')])
+        );
+        assert_eq!(
+            parse("(?i:a)a").unwrap(),
+            Ast::Concat(vec![folded, Ast::Literal('a')])
+        );
+    }
+
+    #[test]
+    fn only_uppercase_literals_are_uppercase() {
+        for pattern in [
+            "\\W",
+            "\\S",
+            "\\D",
+            "\\B",
+            "[[:upper:]]",
+            "[^[:upper:]x]",
+            "(b)\\1",
+        ] {
+            assert!(!parse(pattern).unwrap().has_uppercase(), "{:?}", pattern);
+        }
+        // `\A` is no escape, just an `A`.
+        for pattern in ["[A-Z]", "[Z-a]", "\\A", "x(y|Z)*", "[^[:upper:]É]"] {
+            assert!(parse(pattern).unwrap().has_uppercase(), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_character() {
+        let cases = [
+            (
+                "ab(cd",
+                PatternError::UnmatchedParen {
+                    offset: 2,
+                    column: 3,
+                },
+            ),
+            (
+                "a[bc",
+                PatternError::UnmatchedBracket {
+                    offset: 1,
+                    column: 2,
+                },
+            ),
+            (
+                "a{1",
+                PatternError::UnmatchedBrace {
+                    offset: 1,
+                    column: 2,
+                },
+            ),
+            (
+                "ab\\",
+                PatternError::TrailingBackslash {
+                    offset: 2,
+                    column: 3,
+                },
+            ),
+            (
+                "[z-a]",
+                PatternError::InvalidRange {
+                    offset: 1,
+                    column: 2,
+                },
+            ),
+            (
+                "a{3,2}",
+                PatternError::InvalidRepetition {
+                    offset: 1,
+                    column: 2,
+                },
+            ),
+            (
+                "(a)\\2",
+                PatternError::InvalidBackref {
+                    offset: 3,
+                    column: 4,
+                },
+            ),
+        ];
+        for (pattern, err) in cases {
+            assert_eq!(parse(pattern), Err(err), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn error_columns_count_characters_within_the_line() {
+        assert_eq!(
+            parse("x\néé("),
+            Err(PatternError::UnmatchedParen {
+                offset: 6,
+                column: 3
+            })
+        );
+        assert_eq!(
+            parse("[[:foo:]]"),
+            Err(PatternError::UnknownClass {
+                name: "foo".to_string(),
+                offset: 1,
+                column: 2
+            })
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |n| format!("{}a{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse(&nested(NEST_LIMIT)).is_ok());
+        assert_eq!(
+            parse(&nested(20000)),
+            Err(PatternError::NestingTooDeep {
+                offset: NEST_LIMIT,
+                column: NEST_LIMIT + 1
+            })
+        );
+        let starred = format!("(a){}", "*".repeat(NEST_LIMIT));
+        assert_eq!(
+            parse(&starred),
+            Err(PatternError::NestingTooDeep {
+                offset: 2 + NEST_LIMIT,
+                column: 3 + NEST_LIMIT
+            })
+        );
+    }
+}
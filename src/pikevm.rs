@@ -0,0 +1,289 @@
+//! A Pike VM: simulates the NFA on every thread at once, so the running
+//! time is bounded by the size of the program times the length of the
+//! haystack whatever the pattern looks like.
+//!
+//! It cannot handle backreferences; those programs go to the backtracker.
+
+use std::collections::HashSet;
+
+use crate::compile::{is_look_match, Inst, Prog};
+use crate::sparse::SparseSet;
+use crate::utf8;
+
+/// Scratch space reused across searches with the same program.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    clist: Threads,
+    nlist: Threads,
+    /// The thread being followed through epsilon transitions.
+    thread: Thread,
+}
+
+impl Cache {
+    pub fn new(prog: &Prog) -> Cache {
+        Cache {
+            clist: Threads::new(prog.insts.len()),
+            nlist: Threads::new(prog.insts.len()),
+            thread: Thread {
+                slots: Vec::new(),
+                marks: Vec::new(),
+                stack: Vec::new(),
+            },
+        }
+    }
+}
+
+/// A thread being followed from one position to everything it can reach
+/// there without consuming input.
+#[derive(Clone, Debug)]
+struct Thread {
+    /// Its capture slots.
+    slots: Vec<Option<usize>>,
+    /// The slots of the loop guards it passed a `Mark` of at this
+    /// position. Empty between calls to [`add`].
+    marks: Vec<usize>,
+    stack: Vec<Frame>,
+}
+
+/// The set of live threads at one position, in priority order, with the
+/// capture slots of each.
+///
+/// Only the slots the caller asked for are tracked, and only for the
+/// threads there are, so a search for the overall span of a pattern with
+/// thousands of groups doesn't need room for thousands of slots per
+/// instruction.
+#[derive(Clone, Debug)]
+struct Threads {
+    /// The instructions reached by threads with no loop guards marked.
+    set: SparseSet,
+    /// The instructions reached by threads inside an empty loop iteration,
+    /// with the guards marked. Those threads behave differently at
+    /// `Progress` than the ones in `set`, so they are told apart.
+    marked: HashSet<(usize, Box<[usize]>)>,
+    /// The slots of the thread at `set.dense[i]` start at `i * nslots`.
+    slots: Vec<Option<usize>>,
+    nslots: usize,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            set: SparseSet::new(len),
+            marked: HashSet::new(),
+            slots: Vec::new(),
+            nslots: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.set.clear();
+        self.marked.clear();
+    }
+
+    /// Records that a thread reached `pc` with `marks`, returning false if
+    /// an earlier thread already did. Such a thread has the same future and
+    /// a higher priority, so the later one can be dropped. Threads waiting
+    /// on input or at `Match` forget their marks, so they only go by `pc`.
+    fn visit(&mut self, pc: usize, inst: &Inst, marks: &[usize]) -> bool {
+        let waiting = matches!(
+            inst,
+            Inst::Char(_) | Inst::Any | Inst::Class(_) | Inst::Match
+        );
+        if marks.is_empty() || waiting {
+            return self.set.insert(pc);
+        }
+        let mut marks = marks.to_vec();
+        marks.sort_unstable();
+        self.marked.insert((pc, marks.into_boxed_slice()))
+    }
+
+    /// The slots of the `i`th thread.
+    fn slots(&self, i: usize) -> &[Option<usize>] {
+        &self.slots[i * self.nslots..(i + 1) * self.nslots]
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Frame {
+    Explore(usize),
+    Restore {
+        slot: usize,
+        value: Option<usize>,
+    },
+    /// Forget the most recent mark.
+    Unmark,
+}
+
+/// Searches for the leftmost match starting at or after `start`: among
+/// the matches starting there, the longest with `longest` set, and
+/// otherwise the one the highest priority thread finds (leftmost-first).
+///
+/// On success the capture slots of the match are copied into `slots`. For
+/// a longest match these are the captures of the highest priority thread
+/// reaching its end. With `earliest` set, the search stops at the first
+/// match it sees, which is enough to answer whether there is a match at
+/// all.
+pub fn search(
+    prog: &Prog,
+    cache: &mut Cache,
+    hay: &[u8],
+    start: usize,
+    earliest: bool,
+    longest: bool,
+    slots: &mut [Option<usize>],
+) -> bool {
+    let Cache {
+        clist,
+        nlist,
+        thread,
+    } = cache;
+    // Slot 0 is needed to know where each thread started.
+    let nslots = slots.len().max(2);
+    thread.slots.resize(nslots, None);
+    clist.nslots = nslots;
+    nlist.nslots = nslots;
+    clist.clear();
+    // The span of the best match so far.
+    let mut best: Option<(usize, usize)> = None;
+    let mut at = start;
+    loop {
+        // Start a new, lowest priority thread here unless a match has
+        // already been found further left.
+        if best.is_none() {
+            thread.slots.iter_mut().for_each(|slot| *slot = None);
+            add(prog, hay, clist, thread, 0, at);
+        }
+        if clist.set.dense.is_empty() {
+            break;
+        }
+        let next = utf8::decode(hay, at);
+        nlist.clear();
+        for i in 0..clist.set.dense.len() {
+            let pc = clist.set.dense[i];
+            let inst = &prog.insts[pc];
+            // The other instructions were only passed through, and have no
+            // slots.
+            if !matches!(
+                inst,
+                Inst::Char(_) | Inst::Any | Inst::Class(_) | Inst::Match
+            ) {
+                continue;
+            }
+            // Slot 0 holds where the thread started.
+            let thread_start = clist.slots(i)[0].unwrap_or(at);
+            match inst {
+                Inst::Match => {
+                    let better = match best {
+                        None => true,
+                        Some((best_start, best_end)) => {
+                            !longest
+                                || thread_start < best_start
+                                || (thread_start == best_start && at > best_end)
+                        }
+                    };
+                    if better {
+                        let len = slots.len();
+                        slots.copy_from_slice(&clist.slots(i)[..len]);
+                        best = Some((thread_start, at));
+                    }
+                    if earliest {
+                        return true;
+                    }
+                    // Threads after this one have lower priority, so for a
+                    // leftmost-first match they cannot win.
+                    if !longest {
+                        break;
+                    }
+                }
+                inst => {
+                    // A thread starting right of the best match can only
+                    // find a match that loses to it.
+                    if best.is_some_and(|(best_start, _)| thread_start > best_start) {
+                        continue;
+                    }
+                    if let Some((c, len)) = next {
+                        if inst.matches_char(c) {
+                            thread.slots.copy_from_slice(clist.slots(i));
+                            add(prog, hay, nlist, thread, pc + 1, at + len);
+                        }
+                    }
+                }
+            }
+        }
+        match next {
+            Some((_, len)) => at += len,
+            None => break,
+        }
+        std::mem::swap(clist, nlist);
+    }
+    best.is_some()
+}
+
+/// Adds `thread` at `pc` to `list`, following every epsilon transition
+/// so that only threads waiting on input (or at `Match`) end up in it.
+fn add(prog: &Prog, hay: &[u8], list: &mut Threads, thread: &mut Thread, pc: usize, at: usize) {
+    let Thread {
+        slots: scratch,
+        marks,
+        stack,
+    } = thread;
+    stack.push(Frame::Explore(pc));
+    while let Some(frame) = stack.pop() {
+        let mut pc = match frame {
+            Frame::Explore(pc) => pc,
+            Frame::Restore { slot, value } => {
+                scratch[slot] = value;
+                continue;
+            }
+            Frame::Unmark => {
+                marks.pop();
+                continue;
+            }
+        };
+        while list.visit(pc, &prog.insts[pc], marks) {
+            match &prog.insts[pc] {
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Explore(*second));
+                    pc = *first;
+                }
+                Inst::Jmp(target) => pc = *target,
+                Inst::Save(slot) if *slot < scratch.len() => {
+                    stack.push(Frame::Restore {
+                        slot: *slot,
+                        value: scratch[*slot],
+                    });
+                    scratch[*slot] = Some(at);
+                    pc += 1;
+                }
+                Inst::Save(_) => pc += 1,
+                // As in the backtracker, an empty iteration leaves the
+                // loop, and what follows it takes priority over the
+                // alternatives tried after it.
+                Inst::Mark(slot) => {
+                    marks.push(*slot);
+                    stack.push(Frame::Unmark);
+                    pc += 1;
+                }
+                Inst::Progress { slot, exit } => {
+                    pc = if marks.contains(slot) { *exit } else { pc + 1 };
+                }
+                Inst::Assert(assertion) => {
+                    if !is_look_match(*assertion, hay, at, prog.unicode) {
+                        break;
+                    }
+                    pc += 1;
+                }
+                Inst::Backref(_) => unreachable!("backreferences need the backtracker"),
+                Inst::Char(_) | Inst::Any | Inst::Class(_) | Inst::Match => {
+                    // The thread was just inserted, so it is the last one.
+                    let end = list.set.dense.len() * list.nslots;
+                    if list.slots.len() < end {
+                        list.slots.resize(end, None);
+                    }
+                    list.slots[end - list.nslots..end].copy_from_slice(scratch);
+                    break;
+                }
+            }
+        }
+    }
+}
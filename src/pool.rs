@@ -0,0 +1,123 @@
+//! A work-stealing thread pool.
+//!
+//! Each worker keeps its own queue of tasks and takes the newest one first,
+//! so a worker descending into a directory tree stays depth-first and close
+//! to the data it just touched. Idle workers steal the oldest task from the
+//! others, which is the one most likely to spawn further work.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// The queues shared by the workers of one [`run`].
+struct Queues<T> {
+    queues: Vec<Mutex<VecDeque<T>>>,
+    /// Tasks queued or running. The pool is done when this reaches zero,
+    /// since only a running task can queue more.
+    pending: AtomicUsize,
+    /// The number of tasks queued so far, so that an idle worker can tell
+    /// whether one was queued since it last looked.
+    pushed: Mutex<usize>,
+    /// Wakes idle workers when a task is queued or the last one is done.
+    wake: Condvar,
+}
+
+impl<T> Queues<T> {
+    /// Records that a task has been run, waking every idle worker to
+    /// return if it was the last.
+    fn done(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let _pushed = self.pushed.lock().unwrap();
+            self.wake.notify_all();
+        }
+    }
+}
+
+/// A worker's handle on the pool, through which a task can queue more
+/// tasks.
+pub struct Worker<'p, T> {
+    queues: &'p Queues<T>,
+    index: usize,
+}
+
+impl<'p, T> Worker<'p, T> {
+    /// Queues `task` to be run by this worker or stolen by another.
+    pub fn push(&self, task: T) {
+        self.queues.pending.fetch_add(1, Ordering::SeqCst);
+        self.queues.queues[self.index]
+            .lock()
+            .unwrap()
+            .push_back(task);
+        *self.queues.pushed.lock().unwrap() += 1;
+        self.queues.wake.notify_one();
+    }
+
+    /// Takes the next task to run, waiting while other workers might still
+    /// queue more. Returns `None` once every task has been run.
+    fn next(&self) -> Option<T> {
+        let queues = &self.queues.queues;
+        loop {
+            let seen = *self.queues.pushed.lock().unwrap();
+            if let Some(task) = queues[self.index].lock().unwrap().pop_back() {
+                return Some(task);
+            }
+            let others = (1..queues.len()).map(|i| (self.index + i) % queues.len());
+            for other in others {
+                if let Some(task) = queues[other].lock().unwrap().pop_front() {
+                    return Some(task);
+                }
+            }
+            // Sleep until a task is queued after the ones just looked for,
+            // or none can be any more.
+            let mut pushed = self.queues.pushed.lock().unwrap();
+            while *pushed == seen {
+                if self.queues.pending.load(Ordering::SeqCst) == 0 {
+                    return None;
+                }
+                pushed = self.queues.wake.wait(pushed).unwrap();
+            }
+        }
+    }
+}
+
+/// Runs `tasks`, and any tasks they queue, on one thread per element of
+/// `states`, and returns once all of them are done. `states` must not be
+/// empty.
+///
+/// `work` is called with the running thread's state, which holds whatever
+/// it needs that cannot be shared between threads.
+pub fn run<S, T, F>(states: Vec<S>, tasks: Vec<T>, work: F)
+where
+    S: Send,
+    T: Send,
+    F: Fn(&mut S, T, &Worker<T>) + Sync,
+{
+    let threads = states.len();
+    assert!(threads > 0, "a pool needs at least one thread");
+    let mut queues: Vec<VecDeque<T>> = (0..threads).map(|_| VecDeque::new()).collect();
+    let pending = AtomicUsize::new(tasks.len());
+    // Deal the tasks out in order, so each worker starts on the earliest
+    // ones it has.
+    for (i, task) in tasks.into_iter().enumerate() {
+        queues[i % threads].push_front(task);
+    }
+    let queues = Queues {
+        queues: queues.into_iter().map(Mutex::new).collect(),
+        pending,
+        pushed: Mutex::new(0),
+        wake: Condvar::new(),
+    };
+    let (queues, work) = (&queues, &work);
+    thread::scope(|scope| {
+        for (index, mut state) in states.into_iter().enumerate() {
+            scope.spawn(move || {
+                let worker = Worker { queues, index };
+                while let Some(task) = worker.next() {
+                    work(&mut state, task, &worker);
+                    queues.done();
+                }
+            });
+        }
+    });
+}
@@ -0,0 +1,246 @@
+//! Literal prefilters.
+//!
+//! Most patterns contain a run of literal text that every match has to
+//! include, such as `ERROR: ` in `ERROR: \d+`. Scanning for that text is
+//! much cheaper than running an automaton, so lines without it are rejected
+//! before any engine sees them.
+
+use std::mem;
+
+use crate::ast::Ast;
+
+/// A required literal extracted from a pattern.
+#[derive(Clone, Debug)]
+pub struct Prefilter {
+    needle: Vec<u8>,
+    /// Index of the byte in `needle` least likely to occur in text; that
+    /// is the byte scanned for.
+    rare: usize,
+    /// The pattern is exactly this literal, so finding it is a match.
+    pub exact: bool,
+    /// Every match starts with the literal, so no match can start before
+    /// its first occurrence.
+    pub prefix: bool,
+}
+
+impl Prefilter {
+    /// Builds a prefilter for `ast`, or `None` if it requires no literal.
+    pub fn new(ast: &Ast) -> Option<Prefilter> {
+        let mut runs = Runs::default();
+        runs.visit(ast);
+        runs.flush();
+        let (index, needle) = runs
+            .done
+            .into_iter()
+            .enumerate()
+            .max_by_key(|(_, run)| run.len())?;
+        let needle = needle.into_bytes();
+        let rare = (0..needle.len())
+            .min_by_key(|&i| byte_frequency(needle[i]))
+            .unwrap();
+        Some(Prefilter {
+            exact: is_literal(ast),
+            prefix: index == 0 && runs.first_is_prefix,
+            needle,
+            rare,
+        })
+    }
+
+    /// The length of the literal in bytes.
+    pub fn len(&self) -> usize {
+        self.needle.len()
+    }
+
+    /// Returns the offset of the first occurrence of the literal in `hay`.
+    pub fn find(&self, hay: &[u8]) -> Option<usize> {
+        let len = self.needle.len();
+        let mut at = 0;
+        while at + len <= hay.len() {
+            let found = memchr(
+                self.needle[self.rare],
+                &hay[at + self.rare..hay.len() - len + self.rare + 1],
+            )?;
+            let start = at + found;
+            if &hay[start..start + len] == self.needle.as_slice() {
+                return Some(start);
+            }
+            at = start + 1;
+        }
+        None
+    }
+}
+
+/// Collects the maximal runs of adjacent literals that every match of a
+/// pattern must contain.
+#[derive(Default)]
+struct Runs {
+    current: String,
+    done: Vec<String>,
+    /// Whether anything other than literals in a run consumes input before
+    /// the current position.
+    consumed: bool,
+    /// Whether the first run starts every match.
+    first_is_prefix: bool,
+}
+
+impl Runs {
+    fn flush(&mut self) {
+        if !self.current.is_empty() {
+            if self.done.is_empty() {
+                self.first_is_prefix = !self.consumed;
+            }
+            self.done.push(mem::take(&mut self.current));
+            self.consumed = true;
+        }
+    }
+
+    /// Ends the current run at something that consumes input.
+    fn cut(&mut self) {
+        self.flush();
+        self.consumed = true;
+    }
+
+    fn visit(&mut self, ast: &Ast) {
+        match ast {
+            Ast::Literal(c) => self.current.push(*c),
+            // Zero-width, so the literals on either side stay adjacent.
+            Ast::Empty | Ast::Assertion(_) => {}
+            Ast::Group(group) => self.visit(&group.ast),
+            Ast::Concat(items) => items.iter().for_each(|item| self.visit(item)),
+            Ast::Repetition(rep) if rep.min > 0 => {
+                // The first copy is required, but whatever comes next may
+                // be another copy rather than the following expression.
+                self.flush();
+                self.visit(&rep.ast);
+                self.cut();
+            }
+            Ast::Dot
+            | Ast::Class(_)
+            | Ast::Alternation(_)
+            | Ast::Repetition(_)
+            | Ast::Backref(_) => self.cut(),
+        }
+    }
+}
+
+/// Whether `ast` matches exactly one literal string and nothing else.
+fn is_literal(ast: &Ast) -> bool {
+    match ast {
+        Ast::Literal(_) => true,
+        Ast::Group(group) => is_literal(&group.ast),
+        Ast::Concat(items) => items.iter().all(is_literal),
+        _ => false,
+    }
+}
+
+/// A rough rank of how common a byte is in text and logs; higher is more
+/// common.
+fn byte_frequency(b: u8) -> u8 {
+    match b {
+        b' ' => 255,
+        b'e' | b't' | b'a' | b'o' | b'i' | b'n' | b's' | b'r' | b'h' => 220,
+        b'a'..=b'z' => 180,
+        b'0'..=b'9' => 160,
+        b'A'..=b'Z' => 120,
+        b'\t' | b'.' | b',' | b'-' | b'_' | b':' | b'/' | b'=' => 140,
+        0x21..=0x7E => 80,
+        _ => 20,
+    }
+}
+
+const LO: usize = usize::MAX / 255;
+const HI: usize = LO * 0x80;
+
+/// Returns the offset of the first `needle` byte in `hay`, scanning a
+/// machine word at a time.
+pub fn memchr(needle: u8, hay: &[u8]) -> Option<usize> {
+    const WIDTH: usize = mem::size_of::<usize>();
+    let repeated = LO * needle as usize;
+    let mut at = 0;
+    while at + WIDTH <= hay.len() {
+        let word = usize::from_ne_bytes(hay[at..at + WIDTH].try_into().unwrap());
+        // Classic "has zero byte" test on the bytes equal to `needle`.
+        let equal = word ^ repeated;
+        if equal.wrapping_sub(LO) & !equal & HI != 0 {
+            break;
+        }
+        at += WIDTH;
+    }
+    hay[at..].iter().position(|&b| b == needle).map(|i| at + i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    fn prefilter(pattern: &str) -> Prefilter {
+        Prefilter::new(&parse(pattern).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn memchr_finds_the_first_occurrence_anywhere() {
+        const WIDTH: usize = mem::size_of::<usize>();
+        assert_eq!(memchr(b'x', b""), None);
+        for len in [1, WIDTH - 1, WIDTH, WIDTH + 1, 3 * WIDTH + 3] {
+            let mut hay = vec![b'.'; len];
+            assert_eq!(memchr(b'x', &hay), None, "len {}", len);
+            for at in [0, len / 2, len - 1] {
+                hay[at] = b'x';
+                assert_eq!(memchr(b'x', &hay), Some(at), "len {} at {}", len, at);
+                hay[at] = b'.';
+            }
+        }
+        // Bytes that differ from the needle only in the high bit, and a
+        // needle in the tail after the last whole word.
+        let mut hay = vec![0x80 | b'x'; 2 * WIDTH + 2];
+        assert_eq!(memchr(b'x', &hay), None);
+        hay[2 * WIDTH + 1] = b'x';
+        assert_eq!(memchr(b'x', &hay), Some(2 * WIDTH + 1));
+        assert_eq!(memchr(0, &[1, 2, 0, 0]), Some(2));
+        assert_eq!(memchr(0xFF, &[0xFE; 20]), None);
+    }
+
+    #[test]
+    fn find_checks_the_whole_literal() {
+        let filter = prefilter("abc");
+        assert_eq!(filter.find(b"abc"), Some(0));
+        assert_eq!(filter.find(b"ababcabc"), Some(2));
+        assert_eq!(filter.find(b"xxxxxxxxxxxxxxxxab"), None);
+        assert_eq!(filter.find(b"xxxxxxxxxxxxxxxxabc"), Some(16));
+        assert_eq!(filter.find(b"ab"), None);
+        assert_eq!(filter.find(b""), None);
+        assert_eq!(prefilter("é").find("aé".as_bytes()), Some(1));
+    }
+
+    #[test]
+    fn prefix_and_exact() {
+        // (pattern, needle, prefix, exact)
+        let cases: [(&str, &str, bool, bool); 7] = [
+            ("abc", "abc", true, true),
+            ("(abc)", "abc", true, true),
+            ("x?abc", "abc", false, false),
+            ("(ab){2}c", "ab", true, false),
+            ("a+b", "b", false, false),
+            ("\\babc", "abc", true, false),
+            ("[0-9]+ERROR", "ERROR", false, false),
+        ];
+        for (pattern, needle, prefix, exact) in cases {
+            let filter = prefilter(pattern);
+            assert_eq!(filter.needle, needle.as_bytes(), "{:?}", pattern);
+            assert_eq!(filter.prefix, prefix, "{:?} prefix", pattern);
+            assert_eq!(filter.exact, exact, "{:?} exact", pattern);
+        }
+    }
+
+    #[test]
+    fn patterns_without_a_required_literal() {
+        for pattern in ["a|b", "a*", ".", "[ab]c?", "(a|b)+"] {
+            assert!(
+                Prefilter::new(&parse(pattern).unwrap()).is_none(),
+                "{:?}",
+                pattern
+            );
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! The compiled regular expression type.
+
+use std::cell::RefCell;
+use std::ops::Range;
+
+use crate::ast::{Assertion, Ast};
+use crate::compile::{compile, Config, Prog};
+use crate::error::PatternError;
+use crate::parse::{parse, parse_with, Flags};
+use crate::prefilter::Prefilter;
+use crate::{backtrack, dfa, pikevm, utf8};
+
+/// A compiled extended regular expression.
+///
+/// Matching needs scratch space, which is kept inside the `Regex`; clone it
+/// to search from several threads.
+#[derive(Clone, Debug)]
+pub struct Regex {
+    prog: Prog,
+    kind: MatchKind,
+    prefilter: Option<Prefilter>,
+    dfa: RefCell<dfa::Cache>,
+    /// Created on first use, as the DFA often answers alone.
+    pikevm: RefCell<Option<pikevm::Cache>>,
+}
+
+impl Regex {
+    /// Compiles `pattern` with the default options.
+    pub fn new(pattern: &str) -> Result<Regex, PatternError> {
+        RegexBuilder::new(pattern).build()
+    }
+
+    /// Reports whether the pattern matches anywhere in `hay`.
+    pub fn is_match(&self, hay: &[u8]) -> bool {
+        let mut start = 0;
+        if let Some(prefilter) = &self.prefilter {
+            let Some(found) = prefilter.find(hay) else {
+                return false;
+            };
+            if prefilter.exact {
+                return true;
+            }
+            if prefilter.prefix {
+                start = found;
+            }
+        }
+        if self.prog.has_backrefs {
+            return backtrack::search(&self.prog, hay, start, false, &mut []);
+        }
+        if let Some(matched) = dfa::is_match(&self.prog, &mut self.dfa.borrow_mut(), hay, start) {
+            return matched;
+        }
+        let mut cache = self.pikevm.borrow_mut();
+        let cache = cache.get_or_insert_with(|| pikevm::Cache::new(&self.prog));
+        pikevm::search(&self.prog, cache, hay, start, true, false, &mut [])
+    }
+
+    /// Returns the leftmost match in `hay`, chosen among those starting
+    /// there as the builder's [`MatchKind`] says.
+    pub fn find(&self, hay: &[u8]) -> Option<Match> {
+        self.find_at(hay, 0)
+    }
+
+    /// Like [`find`](Regex::find), but only for matches starting at or
+    /// after `start`. The text before `start` is still seen by `^` and
+    /// `\b`.
+    pub fn find_at(&self, hay: &[u8], start: usize) -> Option<Match> {
+        let mut slots = [None, None];
+        if !self.search(hay, start, &mut slots) {
+            return None;
+        }
+        match slots {
+            [Some(start), Some(end)] => Some(Match { start, end }),
+            _ => None,
+        }
+    }
+
+    /// Returns the leftmost match in `hay` along with the spans of the
+    /// capturing groups in it.
+    ///
+    /// With [`MatchKind::LeftmostLongest`] the overall span is the POSIX
+    /// one, but the groups are those of the first way of matching it in
+    /// the order alternatives are written, rather than the POSIX rule of
+    /// longest subexpressions first.
+    pub fn captures(&self, hay: &[u8]) -> Option<Captures> {
+        self.captures_at(hay, 0)
+    }
+
+    /// Like [`captures`](Regex::captures), but only for matches starting
+    /// at or after `start`.
+    pub fn captures_at(&self, hay: &[u8], start: usize) -> Option<Captures> {
+        let mut slots = vec![None; 2 * self.prog.captures];
+        self.search(hay, start, &mut slots)
+            .then_some(Captures { slots })
+    }
+
+    /// Returns an iterator over the successive non-overlapping matches in
+    /// `hay`.
+    ///
+    /// After an empty match the search resumes one character further on,
+    /// and an empty match right where the previous match ended is skipped,
+    /// so `a*` finds `""`, `"aaa"` and `""` in `"baaab"`.
+    pub fn find_iter<'r, 'h>(&'r self, hay: &'h [u8]) -> Matches<'r, 'h> {
+        Matches {
+            regex: self,
+            hay,
+            at: 0,
+            last_end: None,
+        }
+    }
+
+    /// The number of capturing groups, counting the whole match as group
+    /// 0.
+    pub fn captures_len(&self) -> usize {
+        self.prog.captures
+    }
+
+    /// Searches for a match starting at or after `start`, filling in as
+    /// many capture slots as `slots` has room for.
+    fn search(&self, hay: &[u8], mut start: usize, slots: &mut [Option<usize>]) -> bool {
+        if let Some(prefilter) = &self.prefilter {
+            let Some(found) = prefilter.find(&hay[start..]).map(|i| start + i) else {
+                return false;
+            };
+            // A literal's only match is itself, though an engine still has
+            // to say where its groups are.
+            if prefilter.exact && slots.len() <= 2 {
+                let span = [Some(found), Some(found + prefilter.len())];
+                slots.copy_from_slice(&span[..slots.len()]);
+                return true;
+            }
+            if prefilter.prefix {
+                start = found;
+            }
+        }
+        let longest = self.kind == MatchKind::LeftmostLongest;
+        if self.prog.has_backrefs {
+            backtrack::search(&self.prog, hay, start, longest, slots)
+        } else {
+            // The DFA only answers whether there is a match, but that
+            // rules out most lines cheaply.
+            let matched = dfa::is_match(&self.prog, &mut self.dfa.borrow_mut(), hay, start);
+            if matched == Some(false) {
+                return false;
+            }
+            let mut cache = self.pikevm.borrow_mut();
+            let cache = cache.get_or_insert_with(|| pikevm::Cache::new(&self.prog));
+            pikevm::search(&self.prog, cache, hay, start, false, longest, slots)
+        }
+    }
+}
+
+/// Which match [`Regex::find`] and friends report when several start at
+/// the same leftmost position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchKind {
+    /// The longest, as POSIX and grep do. `a|ab` matches `ab` in `abc`.
+    #[default]
+    LeftmostLongest,
+    /// The one found by trying alternatives in the order they are written
+    /// and repetitions greedily, as Perl does. `a|ab` matches `a` in
+    /// `abc`.
+    LeftmostFirst,
+}
+
+/// The span of a match in a haystack, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    start: usize,
+    end: usize,
+}
+
+impl Match {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// The spans of the capturing groups of a match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    /// Returns the span of group `index`, where group 0 is the whole
+    /// match, or `None` if the group did not take part in the match.
+    pub fn get(&self, index: usize) -> Option<Match> {
+        match self.slots.get(2 * index..2 * index + 2)? {
+            [Some(start), Some(end)] => Some(Match {
+                start: *start,
+                end: *end,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The number of groups, including group 0.
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+/// An iterator over the non-overlapping matches in a haystack, created by
+/// [`Regex::find_iter`].
+pub struct Matches<'r, 'h> {
+    regex: &'r Regex,
+    hay: &'h [u8],
+    /// Where the next search starts.
+    at: usize,
+    /// Where the previous match ended.
+    last_end: Option<usize>,
+}
+
+impl<'r, 'h> Iterator for Matches<'r, 'h> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        loop {
+            if self.at > self.hay.len() {
+                return None;
+            }
+            let found = self.regex.find_at(self.hay, self.at)?;
+            if found.is_empty() {
+                // Step over a whole character, not into the middle of one.
+                self.at = utf8::next_boundary(self.hay, found.end);
+                if self.last_end == Some(found.end) {
+                    continue;
+                }
+            } else {
+                self.at = found.end;
+            }
+            self.last_end = Some(found.end);
+            return Some(found);
+        }
+    }
+}
+
+/// Compiles a [`Regex`] with non-default options.
+#[derive(Clone, Debug)]
+pub struct RegexBuilder {
+    pattern: String,
+    kind: MatchKind,
+    case_insensitive: bool,
+    case_smart: bool,
+    whole_word: bool,
+    whole_line: bool,
+    unicode: bool,
+    size_limit: usize,
+    dfa_size_limit: usize,
+}
+
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> RegexBuilder {
+        RegexBuilder {
+            pattern: pattern.to_string(),
+            kind: MatchKind::default(),
+            case_insensitive: false,
+            case_smart: false,
+            whole_word: false,
+            whole_line: false,
+            unicode: true,
+            size_limit: 10 * (1 << 20),
+            dfa_size_limit: 2 * (1 << 20),
+        }
+    }
+
+    /// Which of the matches starting at the leftmost position to report.
+    /// Defaults to [`MatchKind::LeftmostLongest`].
+    pub fn match_kind(&mut self, kind: MatchKind) -> &mut RegexBuilder {
+        self.kind = kind;
+        self
+    }
+
+    /// Whether to ignore case, as if the pattern started with `(?i)`.
+    /// Characters match if they have the same simple Unicode case folding,
+    /// so `s` also matches `S` and `ſ`. Disabled by default.
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// Whether to ignore case only if the pattern has no uppercase
+    /// literal characters, so `foo` matches `FOO` but `Foo` does not.
+    /// Escapes and classes such as `\W` or `[[:upper:]]` don't count.
+    /// Overrides [`case_insensitive`](RegexBuilder::case_insensitive) when
+    /// enabled. Disabled by default.
+    pub fn case_smart(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.case_smart = yes;
+        self
+    }
+
+    /// Whether to only report matches that are whole words: neither
+    /// preceded nor followed by a word character. As with `grep -w`, a
+    /// match that isn't is no reason to give up; `foo|foobar` still finds
+    /// the `foo` in `foobarx foo`. Disabled by default.
+    pub fn whole_word(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.whole_word = yes;
+        self
+    }
+
+    /// Whether to only report matches spanning the whole haystack, apart
+    /// from a trailing newline, as if the pattern were wrapped in `^(...)$`
+    /// without adding a group. Disabled by default.
+    pub fn whole_line(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.whole_line = yes;
+        self
+    }
+
+    /// Whether `\d`, `\w` and `\s` (and their negations) match Unicode
+    /// digits, letters and whitespace, or only their ASCII counterparts.
+    /// Enabled by default.
+    pub fn unicode(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.unicode = yes;
+        self
+    }
+
+    /// Sets the approximate size, in bytes, that the compiled program may
+    /// reach before [`PatternError::TooBig`] is returned. Defaults to 10 MiB.
+    pub fn size_limit(&mut self, bytes: usize) -> &mut RegexBuilder {
+        self.size_limit = bytes;
+        self
+    }
+
+    /// Sets the approximate memory, in bytes, that the lazy DFA may use for
+    /// its state cache before clearing it. Defaults to 2 MiB.
+    pub fn dfa_size_limit(&mut self, bytes: usize) -> &mut RegexBuilder {
+        self.dfa_size_limit = bytes;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, PatternError> {
+        let mut ast = parse(&self.pattern)?;
+        let case_insensitive = match self.case_smart {
+            true => !ast.has_uppercase(),
+            false => self.case_insensitive,
+        };
+        if case_insensitive {
+            let flags = Flags { case_insensitive };
+            ast = parse_with(&self.pattern, flags)?;
+        }
+        if self.whole_word {
+            ast = surround(ast, Assertion::NoWordBefore, Assertion::NoWordAfter);
+        }
+        if self.whole_line {
+            ast = surround(ast, Assertion::StartLine, Assertion::EndLine);
+        }
+        let config = Config {
+            unicode: self.unicode,
+            size_limit: self.size_limit,
+        };
+        let prog = compile(&ast, config)?;
+        Ok(Regex {
+            kind: self.kind,
+            prefilter: Prefilter::new(&ast),
+            dfa: RefCell::new(dfa::Cache::new(&prog, self.dfa_size_limit)),
+            pikevm: RefCell::new(None),
+            prog,
+        })
+    }
+}
+
+/// Puts `ast` between two assertions.
+fn surround(ast: Ast, before: Assertion, after: Assertion) -> Ast {
+    Ast::Concat(vec![Ast::Assertion(before), ast, Ast::Assertion(after)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, kind: MatchKind, hay: &str) -> Option<Range<usize>> {
+        let regex = RegexBuilder::new(pattern).match_kind(kind).build().unwrap();
+        regex.find(hay.as_bytes()).map(|m| m.range())
+    }
+
+    fn spans(pattern: &str, hay: &str) -> Vec<Range<usize>> {
+        let regex = Regex::new(pattern).unwrap();
+        regex.find_iter(hay.as_bytes()).map(|m| m.range()).collect()
+    }
+
+    #[test]
+    fn longest_and_first_matches() {
+        let cases = [
+            ("a|ab", "abc", 0..2, 0..1),
+            ("ab|a", "abc", 0..2, 0..2),
+            ("a*?", "aaa", 0..3, 0..3),
+            ("(ab|a)(c|bcd)", "abcd", 0..4, 0..3),
+            ("x*|b", "abc", 0..0, 0..0),
+            ("a+|(a|b)+", "aab", 0..3, 0..2),
+            ("(a|ab)\\1*", "ababab", 0..6, 0..1),
+        ];
+        for (pattern, hay, longest, first) in cases {
+            assert_eq!(
+                find(pattern, MatchKind::LeftmostLongest, hay),
+                Some(longest),
+                "{:?} longest",
+                pattern
+            );
+            assert_eq!(
+                find(pattern, MatchKind::LeftmostFirst, hay),
+                Some(first),
+                "{:?} first",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn leftmost_wins_over_longer() {
+        assert_eq!(
+            find("b+|abc", MatchKind::LeftmostLongest, "abbb"),
+            Some(1..4)
+        );
+        assert_eq!(find("bc|b", MatchKind::LeftmostFirst, "abc"), Some(1..3));
+    }
+
+    #[test]
+    fn find_iter_steps_past_empty_matches() {
+        assert_eq!(spans("a*", "baaab"), [0..0, 1..4, 5..5]);
+        assert_eq!(spans("x*", "ab"), [0..0, 1..1, 2..2]);
+        assert_eq!(spans("", "a"), [0..0, 1..1]);
+    }
+
+    #[test]
+    fn find_iter_steps_over_whole_characters() {
+        assert_eq!(spans("x*", "é"), [0..0, 2..2]);
+        assert_eq!(spans("\\b", "é ü"), [0..0, 2..2, 3..3, 5..5]);
+    }
+
+    #[test]
+    fn captures_follow_the_reported_span() {
+        let regex = Regex::new("(a|ab)(c|bcd)(d*)").unwrap();
+        let caps = regex.captures(b"abcd").unwrap();
+        assert_eq!(caps.get(0).map(|m| m.range()), Some(0..4));
+        assert_eq!(caps.get(1).map(|m| m.range()), Some(0..1));
+        assert_eq!(caps.get(2).map(|m| m.range()), Some(1..4));
+        assert_eq!(caps.get(3).map(|m| m.range()), Some(4..4));
+    }
+
+    #[test]
+    fn deepest_allowed_nesting_compiles_and_matches() {
+        let n = 250;
+        let pattern = format!("{}a*{}", "(".repeat(n - 1), ")".repeat(n - 1));
+        let regex = Regex::new(&pattern).unwrap();
+        assert_eq!(regex.find(b"baa").map(|m| m.range()), Some(0..0));
+        assert_eq!(
+            regex.captures(b"aa").unwrap().get(n - 1).map(|m| m.range()),
+            Some(0..2)
+        );
+    }
+
+    #[test]
+    fn whole_words_and_lines_try_every_branch() {
+        let build = |pattern: &str, kind, word, line| {
+            RegexBuilder::new(pattern)
+                .match_kind(kind)
+                .whole_word(word)
+                .whole_line(line)
+                .build()
+                .unwrap()
+        };
+        for kind in [MatchKind::LeftmostLongest, MatchKind::LeftmostFirst] {
+            let word = build("foo|foobar", kind, true, false);
+            let found = |hay: &str| word.find(hay.as_bytes()).map(|m| m.range());
+            assert_eq!(found("foobarx foo"), Some(8..11), "{:?}", kind);
+            assert_eq!(found("foobar foo"), Some(0..6), "{:?}", kind);
+            assert_eq!(found("xfoo foobarx"), None, "{:?}", kind);
+
+            // The anchors go around the whole alternation, not its ends.
+            let line = build("foo|foobar", kind, false, true);
+            let found = |hay: &str| line.find(hay.as_bytes()).map(|m| m.range());
+            assert_eq!(found("foobar"), Some(0..6), "{:?}", kind);
+            assert_eq!(found("foo\n"), Some(0..3), "{:?}", kind);
+            assert_eq!(found("foox"), None, "{:?}", kind);
+            assert_eq!(found("xfoobar"), None, "{:?}", kind);
+            assert_eq!(line.captures_len(), 1);
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! Line-oriented searching of a byte stream.
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use crate::color::Colors;
+use crate::regex::Regex;
+
+/// Which lines are selected and how they are reported.
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    /// Select the lines the pattern doesn't match (`-v`).
+    pub invert: bool,
+    /// Write only the number of selected lines (`-c`).
+    pub count: bool,
+    /// Prefix each line with its 1-based line number (`-n`).
+    pub line_number: bool,
+    /// Prefix each line with the 1-based byte column of the first match
+    /// in it, if there is one (`--column`).
+    pub column: bool,
+    /// Prefix each line with the byte offset of its start in the input
+    /// (`-b`).
+    pub byte_offset: bool,
+    /// Write each non-empty match on a line of its own instead of the
+    /// lines containing them (`-o`). Positions are then those of the
+    /// matches.
+    pub only_matching: bool,
+    /// Highlight matches and positions with these colors (`--color`).
+    pub colors: Option<Colors>,
+    /// The number of lines of leading context to print before each
+    /// selected line (`-B`).
+    pub before_context: usize,
+    /// The number of lines of trailing context to print after each
+    /// selected line (`-A`).
+    pub after_context: usize,
+    /// The line printed between groups of lines that are not adjacent
+    /// when context is printed; `None` prints nothing there
+    /// (`--no-group-separator`).
+    pub group_separator: Option<String>,
+}
+
+impl SearchOptions {
+    /// Returns the group separator as written out, newline included, or
+    /// `None` if no separators are printed. Besides the groups within one
+    /// input, it separates the output of different inputs.
+    pub fn group_separator(&self) -> Option<Vec<u8>> {
+        let context = self.before_context > 0 || self.after_context > 0;
+        if !context || self.count {
+            return None;
+        }
+        let separator = self.group_separator.as_ref()?;
+        let mut line = Vec::new();
+        match &self.colors {
+            Some(colors) => colors
+                .paint(&mut line, &colors.separator, separator.as_bytes())
+                .unwrap(),
+            None => line.extend_from_slice(separator.as_bytes()),
+        }
+        line.push(b'\n');
+        Some(line)
+    }
+}
+
+/// Reads lines from an input and writes the ones the pattern selects.
+pub struct Searcher<'r> {
+    regex: &'r Regex,
+    options: &'r SearchOptions,
+    line: Vec<u8>,
+    /// The most recent lines that were not printed, at most
+    /// `before_context` of them, kept for leading context.
+    before: VecDeque<Line>,
+}
+
+/// A line kept for leading context.
+struct Line {
+    number: u64,
+    offset: u64,
+    /// The line with its newline, if it had one.
+    bytes: Vec<u8>,
+}
+
+impl<'r> Searcher<'r> {
+    pub fn new(regex: &'r Regex, options: &'r SearchOptions) -> Searcher<'r> {
+        Searcher {
+            regex,
+            options,
+            line: Vec::new(),
+            before: VecDeque::with_capacity(options.before_context),
+        }
+    }
+
+    /// Searches `reader` line by line, writing every selected line to
+    /// `out`, or their number with `count` set. Lines are prefixed with
+    /// `filename`, if one is given, and whichever positions the options ask
+    /// for, in the order file, line, column, byte offset and each followed
+    /// by a colon, or a `-` for context lines. Only the current line and
+    /// the leading context are held in memory. Returns whether any line was
+    /// selected.
+    pub fn search<R: BufRead, W: Write>(
+        &mut self,
+        mut reader: R,
+        out: &mut W,
+        filename: Option<&str>,
+    ) -> io::Result<bool> {
+        let Searcher {
+            regex,
+            options,
+            line: buffer,
+            before,
+        } = self;
+        let printer = Printer {
+            regex,
+            options,
+            filename,
+            separator: options.group_separator(),
+        };
+        let (before_context, after_context) = match options.count {
+            true => (0, 0),
+            false => (options.before_context, options.after_context),
+        };
+        before.clear();
+        let mut count = 0u64;
+        let mut line_number = 0u64;
+        let mut offset = 0u64;
+        let mut last_printed = None;
+        // The number of lines of trailing context still to print.
+        let mut after = 0;
+        loop {
+            buffer.clear();
+            let read = reader.read_until(b'\n', buffer)?;
+            if read == 0 {
+                break;
+            }
+            line_number += 1;
+            let line_offset = offset;
+            offset += read as u64;
+            let line = buffer.strip_suffix(b"\n").unwrap_or(buffer);
+            // Finding where the match starts costs more than finding
+            // whether there is one, so only do it when it is printed.
+            let (matched, column) = if options.column && !options.count {
+                let found = regex.find(line);
+                (found.is_some(), found.map(|m| m.start() + 1))
+            } else {
+                (regex.is_match(line), None)
+            };
+            if matched == options.invert {
+                if after > 0 {
+                    after -= 1;
+                    printer.context(out, &mut last_printed, line_number, line_offset, line)?;
+                } else if before_context > 0 {
+                    // Keep the line for leading context, reusing the buffer
+                    // of the oldest one.
+                    let mut bytes = match before.len() == before_context {
+                        true => before.pop_front().unwrap().bytes,
+                        false => Vec::new(),
+                    };
+                    mem::swap(&mut bytes, buffer);
+                    before.push_back(Line {
+                        number: line_number,
+                        offset: line_offset,
+                        bytes,
+                    });
+                }
+                continue;
+            }
+            count += 1;
+            if options.count {
+                continue;
+            }
+            for kept in before.drain(..) {
+                let line = kept.bytes.strip_suffix(b"\n").unwrap_or(&kept.bytes);
+                printer.context(out, &mut last_printed, kept.number, kept.offset, line)?;
+            }
+            after = after_context;
+            let prefix = Prefix {
+                filename,
+                line_number: options.line_number.then_some(line_number),
+                column,
+                offset: options.byte_offset.then_some(line_offset),
+                separator: b':',
+            };
+            printer.selected(out, &mut last_printed, line_number, prefix, line)?;
+        }
+        if options.count {
+            let prefix = Prefix {
+                filename,
+                line_number: None,
+                column: None,
+                offset: None,
+                separator: b':',
+            };
+            prefix.write(out, options.colors.as_ref())?;
+            writeln!(out, "{}", count)?;
+        }
+        Ok(count > 0)
+    }
+}
+
+/// Writes the lines of one search.
+struct Printer<'a> {
+    regex: &'a Regex,
+    options: &'a SearchOptions,
+    filename: Option<&'a str>,
+    /// The line between groups of context, if context is printed.
+    separator: Option<Vec<u8>>,
+}
+
+impl<'a> Printer<'a> {
+    /// Writes the group separator if the line numbered `number` doesn't
+    /// directly follow the last one printed.
+    fn separate<W: Write>(
+        &self,
+        out: &mut W,
+        last_printed: &mut Option<u64>,
+        number: u64,
+    ) -> io::Result<()> {
+        if let (Some(separator), Some(last)) = (&self.separator, *last_printed) {
+            if number > last + 1 {
+                out.write_all(separator)?;
+            }
+        }
+        *last_printed = Some(number);
+        Ok(())
+    }
+
+    fn context<W: Write>(
+        &self,
+        out: &mut W,
+        last_printed: &mut Option<u64>,
+        number: u64,
+        offset: u64,
+        line: &[u8],
+    ) -> io::Result<()> {
+        self.separate(out, last_printed, number)?;
+        // As in GNU grep, -o prints no context lines, but still separates
+        // the groups they would form.
+        if self.options.only_matching {
+            return Ok(());
+        }
+        let prefix = Prefix {
+            filename: self.filename,
+            line_number: self.options.line_number.then_some(number),
+            column: None,
+            offset: self.options.byte_offset.then_some(offset),
+            separator: b'-',
+        };
+        prefix.write(out, self.options.colors.as_ref())?;
+        out.write_all(line)?;
+        out.write_all(b"\n")
+    }
+
+    fn selected<W: Write>(
+        &self,
+        out: &mut W,
+        last_printed: &mut Option<u64>,
+        number: u64,
+        prefix: Prefix,
+        line: &[u8],
+    ) -> io::Result<()> {
+        let colors = self.options.colors.as_ref();
+        self.separate(out, last_printed, number)?;
+        if !self.options.only_matching {
+            prefix.write(out, colors)?;
+            match colors {
+                // Inverted lines have no matches to highlight.
+                Some(colors) if !self.options.invert => {
+                    let mut last = 0;
+                    for found in self.regex.find_iter(line).filter(|m| !m.is_empty()) {
+                        out.write_all(&line[last..found.start()])?;
+                        colors.paint(out, &colors.matched, &line[found.range()])?;
+                        last = found.end();
+                    }
+                    out.write_all(&line[last..])?;
+                }
+                _ => out.write_all(line)?,
+            }
+            return out.write_all(b"\n");
+        }
+        // Selected lines without a match, as with -v, print nothing.
+        for found in self.regex.find_iter(line).filter(|m| !m.is_empty()) {
+            let prefix = Prefix {
+                column: prefix.column.map(|_| found.start() + 1),
+                offset: prefix.offset.map(|offset| offset + found.start() as u64),
+                ..prefix
+            };
+            prefix.write(out, colors)?;
+            match colors {
+                Some(colors) => colors.paint(out, &colors.matched, &line[found.range()])?,
+                None => out.write_all(&line[found.range()])?,
+            }
+            out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// The positions written before an output line.
+#[derive(Clone, Copy)]
+struct Prefix<'a> {
+    filename: Option<&'a str>,
+    line_number: Option<u64>,
+    column: Option<usize>,
+    offset: Option<u64>,
+    /// Follows each field: `:` for selected lines, `-` for context.
+    separator: u8,
+}
+
+impl<'a> Prefix<'a> {
+    fn write<W: Write>(&self, out: &mut W, colors: Option<&Colors>) -> io::Result<()> {
+        if let Some(filename) = self.filename {
+            self.field(out, colors, |colors| &colors.filename, filename)?;
+        }
+        if let Some(line_number) = self.line_number {
+            self.field(out, colors, |colors| &colors.line_number, line_number)?;
+        }
+        if let Some(column) = self.column {
+            self.field(out, colors, |colors| &colors.line_number, column)?;
+        }
+        if let Some(offset) = self.offset {
+            self.field(out, colors, |colors| &colors.byte_offset, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one field and the separator after it.
+    fn field<W: Write>(
+        &self,
+        out: &mut W,
+        colors: Option<&Colors>,
+        color: fn(&Colors) -> &String,
+        value: impl Display,
+    ) -> io::Result<()> {
+        match colors {
+            Some(colors) => {
+                let text = value.to_string();
+                colors.paint(out, color(colors), text.as_bytes())?;
+                colors.paint(out, &colors.separator, &[self.separator])
+            }
+            None => {
+                write!(out, "{}", value)?;
+                out.write_all(&[self.separator])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "a\nmatch 1\nb\nc\nmatch 2\nd\ne\nf\ng\nmatch 3\nh\n";
+
+    fn search(pattern: &str, options: &SearchOptions, input: &str) -> String {
+        let regex = Regex::new(pattern).unwrap();
+        let mut out = Vec::new();
+        Searcher::new(&regex, options)
+            .search(input.as_bytes(), &mut out, None)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn context(before: usize, after: usize) -> SearchOptions {
+        SearchOptions {
+            before_context: before,
+            after_context: after,
+            group_separator: Some("--".to_string()),
+            ..SearchOptions::default()
+        }
+    }
+
+    #[test]
+    fn adjacent_context_merges_into_one_group() {
+        let options = SearchOptions {
+            line_number: true,
+            ..context(1, 1)
+        };
+        assert_eq!(
+            search("match", &options, INPUT),
+            "1-a\n2:match 1\n3-b\n4-c\n5:match 2\n6-d\n--\n9-g\n10:match 3\n11-h\n"
+        );
+        // Overlapping context is printed once.
+        assert_eq!(
+            search("match", &context(3, 0), INPUT),
+            "a\nmatch 1\nb\nc\nmatch 2\n--\ne\nf\ng\nmatch 3\n"
+        );
+        // Selected lines next to each other need no context to join.
+        assert_eq!(
+            search("^[bch]$", &context(0, 1), INPUT),
+            "b\nc\nmatch 2\n--\nh\n"
+        );
+    }
+
+    #[test]
+    fn group_separator_can_change_or_go() {
+        let options = SearchOptions {
+            group_separator: Some("~~".to_string()),
+            ..context(2, 1)
+        };
+        assert_eq!(
+            search("match", &options, INPUT),
+            "a\nmatch 1\nb\nc\nmatch 2\nd\n~~\nf\ng\nmatch 3\nh\n"
+        );
+        let options = SearchOptions {
+            group_separator: None,
+            ..context(1, 1)
+        };
+        assert_eq!(
+            search("match", &options, INPUT),
+            "a\nmatch 1\nb\nc\nmatch 2\nd\ng\nmatch 3\nh\n"
+        );
+        // Without context there are no groups to separate.
+        assert_eq!(
+            search("match", &context(0, 0), INPUT),
+            "match 1\nmatch 2\nmatch 3\n"
+        );
+    }
+
+    #[test]
+    fn only_matching_separates_groups_without_context() {
+        let options = SearchOptions {
+            line_number: true,
+            only_matching: true,
+            ..context(1, 1)
+        };
+        assert_eq!(
+            search("match [0-9]", &options, INPUT),
+            "2:match 1\n5:match 2\n--\n10:match 3\n"
+        );
+    }
+
+    #[test]
+    fn returns_whether_a_line_was_selected() {
+        let regex = Regex::new("match").unwrap();
+        let options = SearchOptions::default();
+        let mut searcher = Searcher::new(&regex, &options);
+        assert!(searcher
+            .search(INPUT.as_bytes(), &mut Vec::new(), None)
+            .unwrap());
+        assert!(!searcher
+            .search(&b"a\nb\n"[..], &mut Vec::new(), None)
+            .unwrap());
+    }
+
+    #[test]
+    fn count_of_inverted_lines() {
+        let options = SearchOptions {
+            invert: true,
+            count: true,
+            ..context(1, 1)
+        };
+        assert_eq!(search("match", &options, INPUT), "8\n");
+        assert_eq!(search(".", &options, INPUT), "0\n");
+        // The last line counts without its newline, and empty lines count.
+        assert_eq!(search("x", &options, "x\n\nx"), "1\n");
+
+        let regex = Regex::new("match").unwrap();
+        let mut out = Vec::new();
+        let selected = Searcher::new(&regex, &options)
+            .search(INPUT.as_bytes(), &mut out, Some("file"))
+            .unwrap();
+        assert!(selected);
+        assert_eq!(out, b"file:8\n");
+    }
+
+    #[test]
+    fn prefixes_come_in_a_fixed_order() {
+        let regex = Regex::new("foo").unwrap();
+        let options = SearchOptions {
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            ..context(1, 0)
+        };
+        let mut out = Vec::new();
+        Searcher::new(&regex, &options)
+            .search(&b"ab foo\nbar\nfoo\n"[..], &mut out, Some("file"))
+            .unwrap();
+        // File, line, column and byte offset; context lines have no column.
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "file:1:4:0:ab foo\nfile-2-7-bar\nfile:3:1:11:foo\n"
+        );
+        let options = SearchOptions {
+            line_number: true,
+            byte_offset: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(search("foo", &options, "ab foo\n"), "1:0:ab foo\n");
+    }
+
+    #[test]
+    fn only_matching_positions_are_those_of_the_matches() {
+        let options = SearchOptions {
+            byte_offset: true,
+            only_matching: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            search("foo", &options, "ab foo foo\nfoo\n"),
+            "3:foo\n7:foo\n11:foo\n"
+        );
+        let options = SearchOptions {
+            line_number: true,
+            column: true,
+            ..options
+        };
+        assert_eq!(
+            search("foo", &options, "ab foo foo\nfoo\n"),
+            "1:4:3:foo\n1:8:7:foo\n2:1:11:foo\n"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! A set of small integers with constant-time insertion and clearing.
+
+/// An ordered set of instruction indexes with constant-time clearing.
+#[derive(Clone, Debug)]
+pub struct SparseSet {
+    pub dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
+
+impl SparseSet {
+    pub fn new(capacity: usize) -> SparseSet {
+        SparseSet {
+            dense: Vec::with_capacity(capacity),
+            sparse: vec![0; capacity],
+        }
+    }
+
+    pub fn contains(&self, value: usize) -> bool {
+        let i = self.sparse[value];
+        i < self.dense.len() && self.dense[i] == value
+    }
+
+    /// Inserts `value`, returning false if it was already present.
+    pub fn insert(&mut self, value: usize) -> bool {
+        if self.contains(value) {
+            return false;
+        }
+        self.sparse[value] = self.dense.len();
+        self.dense.push(value);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.dense.clear();
+    }
+}
@@ -0,0 +1,49 @@
+//! UTF-8 decoding over raw byte haystacks.
+//!
+//! Lines read from files are not guaranteed to be valid UTF-8, so the
+//! engines work on bytes and decode characters as they go. Each invalid
+//! byte decodes to U+FFFD on its own.
+
+const REPLACEMENT: char = '\u{FFFD}';
+
+/// Decodes the character starting at `at`, returning it and its length in
+/// bytes, or `None` at the end of the haystack.
+pub fn decode(hay: &[u8], at: usize) -> Option<(char, usize)> {
+    let first = *hay.get(at)?;
+    if first < 0x80 {
+        return Some((first as char, 1));
+    }
+    let width = match first {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return Some((REPLACEMENT, 1)),
+    };
+    match hay.get(at..at + width).map(std::str::from_utf8) {
+        Some(Ok(s)) => Some((s.chars().next().unwrap(), width)),
+        _ => Some((REPLACEMENT, 1)),
+    }
+}
+
+/// Returns the byte offset just past the character starting at `at`.
+pub fn next_boundary(hay: &[u8], at: usize) -> usize {
+    at + decode(hay, at).map_or(1, |(_, len)| len)
+}
+
+/// Decodes the character ending just before `end`, or `None` at the start
+/// of the haystack.
+pub fn decode_last(hay: &[u8], end: usize) -> Option<(char, usize)> {
+    if end == 0 {
+        return None;
+    }
+    // Walk back over at most three continuation bytes to the leading byte.
+    let lowest = end.saturating_sub(4);
+    let mut start = end - 1;
+    while start > lowest && hay[start] & 0xC0 == 0x80 {
+        start -= 1;
+    }
+    match decode(hay, start) {
+        Some((c, len)) if start + len == end => Some((c, len)),
+        _ => Some((REPLACEMENT, 1)),
+    }
+}
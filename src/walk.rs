@@ -0,0 +1,173 @@
+//! Recursive directory traversal for `-r` and `-R`.
+
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::ignore::Ignore;
+
+/// How directories are traversed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WalkOptions {
+    /// Follow symbolic links found inside directories (`-R`). Links named
+    /// on the command line are always followed.
+    pub follow_links: bool,
+    /// Visit directory entries in byte order of their names instead of the
+    /// order the file system returns them in.
+    pub sort: bool,
+    /// Search hidden files and directories, whose names start with a dot
+    /// (`--hidden`).
+    pub hidden: bool,
+    /// Disregard `.gitignore` and `.ignore` files (`--no-ignore`).
+    pub no_ignore: bool,
+}
+
+#[derive(Debug)]
+pub enum WalkError {
+    /// A file or directory could not be inspected or read.
+    Io { path: PathBuf, err: io::Error },
+    /// A directory contains itself through a symbolic link.
+    Loop { path: PathBuf },
+}
+
+/// Something found by a walk: a file to search or a directory to read.
+#[derive(Debug)]
+pub enum WalkEntry {
+    File(PathBuf),
+    Dir(WalkDir),
+}
+
+impl WalkEntry {
+    /// Classifies a path named on the command line. Unlike the entries
+    /// found below it, it is never skipped, but a directory starts out with
+    /// the ignore rules of the repository it is in.
+    pub fn root(path: &Path, options: &WalkOptions) -> Result<WalkEntry, WalkError> {
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_dir() => Ok(WalkEntry::Dir(WalkDir {
+                path: path.to_path_buf(),
+                ignore: match options.no_ignore {
+                    true => Arc::new(Ignore::default()),
+                    false => Ignore::root(path),
+                },
+                ancestors: vec![file_id(path, &meta)],
+            })),
+            Ok(_) => Ok(WalkEntry::File(path.to_path_buf())),
+            Err(err) => Err(WalkError::Io {
+                path: path.to_path_buf(),
+                err,
+            }),
+        }
+    }
+}
+
+/// A directory found by a walk, not yet read.
+///
+/// Reading one yields the directories below it as further `WalkDir`s, so a
+/// walk can be split between threads one directory at a time.
+#[derive(Debug)]
+pub struct WalkDir {
+    path: PathBuf,
+    /// The rules in effect in the parent directory.
+    ignore: Arc<Ignore>,
+    /// This directory and the ones it was reached through, for loop
+    /// detection.
+    ancestors: Vec<FileId>,
+}
+
+impl WalkDir {
+    /// Lists the files and directories in this one that the walk should
+    /// visit. Errors about single entries are returned in their place.
+    ///
+    /// Devices, FIFOs and sockets are skipped, as GNU grep does by default.
+    /// With `follow_links` unset, symbolic links are skipped too, and so
+    /// are hidden and ignored entries unless the options say otherwise.
+    pub fn read(
+        &self,
+        options: &WalkOptions,
+    ) -> Result<Vec<Result<WalkEntry, WalkError>>, WalkError> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.path)
+            .and_then(|dir| dir.map(|entry| entry.map(|entry| entry.path())).collect())
+            .map_err(|err| WalkError::Io {
+                path: self.path.clone(),
+                err,
+            })?;
+        if options.sort {
+            paths.sort_unstable();
+        }
+        let ignore = match options.no_ignore {
+            true => Arc::clone(&self.ignore),
+            false => self.ignore.add_dir(&self.path),
+        };
+        let entries = paths
+            .into_iter()
+            .filter_map(|path| self.entry(path, &ignore, options).transpose())
+            .collect();
+        Ok(entries)
+    }
+
+    /// Classifies one entry of this directory, or returns `None` if the
+    /// walk skips it.
+    fn entry(
+        &self,
+        path: PathBuf,
+        ignore: &Arc<Ignore>,
+        options: &WalkOptions,
+    ) -> Result<Option<WalkEntry>, WalkError> {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden && !options.hidden {
+            return Ok(None);
+        }
+        let mut meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(err) => return Err(WalkError::Io { path, err }),
+        };
+        if meta.file_type().is_symlink() {
+            if !options.follow_links {
+                return Ok(None);
+            }
+            meta = match fs::metadata(&path) {
+                Ok(meta) => meta,
+                Err(err) => return Err(WalkError::Io { path, err }),
+            };
+        }
+        if ignore.is_ignored(&path, meta.is_dir()) {
+            return Ok(None);
+        }
+        if meta.is_dir() {
+            let id = file_id(&path, &meta);
+            if self.ancestors.contains(&id) {
+                return Err(WalkError::Loop { path });
+            }
+            let mut ancestors = self.ancestors.clone();
+            ancestors.push(id);
+            return Ok(Some(WalkEntry::Dir(WalkDir {
+                path,
+                ignore: Arc::clone(ignore),
+                ancestors,
+            })));
+        }
+        Ok(meta.is_file().then_some(WalkEntry::File(path)))
+    }
+}
+
+/// Identifies a directory for loop detection, even when it is reached
+/// through different paths.
+#[cfg(unix)]
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(_path: &Path, meta: &Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _meta: &Metadata) -> FileId {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}